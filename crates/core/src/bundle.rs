@@ -0,0 +1,266 @@
+//! A portable file format containing [Change]s and the registry contents
+//! they use.
+//!
+//! A bundle starts with [`MAGIC`] and the format [`VERSION`], followed by the
+//! borsh encoded changes and contents, and ends with the SHA-256 hash of
+//! everything before it.
+
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Read, Write};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::change::{Change, ChangeContent, ChangeHash};
+use crate::registry::{ContentHash, Registry};
+use crate::repository::Repository;
+
+/// The bytes every bundle starts with.
+pub const MAGIC: &[u8; 8] = b"SOLIPRBD";
+
+/// The version of the bundle format.
+pub const VERSION: u8 = 1;
+
+/// An error that can happen while reading a [Bundle].
+#[derive(Debug, Error)]
+pub enum BundleReadError {
+    /// An io error.
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+
+    /// The data does not start with [`MAGIC`].
+    #[error("the data is not a bundle")]
+    NotABundle,
+
+    /// The bundle was created with an unknown version of the format.
+    #[error("unsupported bundle version: {0}")]
+    UnsupportedVersion(u8),
+
+    /// The bundle does not match its checksum.
+    #[error("the bundle is corrupted")]
+    Corrupted,
+
+    /// A content of the bundle does not match its hash.
+    #[error("the content does not match its hash: {0}")]
+    InvalidContent(ContentHash),
+}
+
+/// An error that can happen while moving a [Bundle] from or to a
+/// [Repository] and a [Registry].
+#[derive(Debug, Error)]
+pub enum BundleError<RepoError, RegError> {
+    /// An error that can happen while using the repository.
+    #[error("repository error: {0}")]
+    Repository(RepoError),
+
+    /// An error that can happen while using the registry.
+    #[error("registry error: {0}")]
+    Registry(RegError),
+
+    /// A content used by a change is neither in the bundle nor in the
+    /// registry.
+    #[error("content not found in the registry: {0}")]
+    NotFound(ContentHash),
+
+    /// The change from which the bundle should start is not in the
+    /// repository.
+    #[error("change not found in the repository: {0}")]
+    ChangeNotFound(ChangeHash),
+
+    /// An io error.
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// A set of [Change]s along with the registry contents they use.
+#[derive(Debug, Clone, Default, PartialEq, Eq, BorshDeserialize, BorshSerialize)]
+pub struct Bundle {
+    /// The changes of the bundle.
+    changes: Vec<Change>,
+
+    /// The contents of the bundle by hash.
+    contents: BTreeMap<ContentHash, Vec<u8>>,
+}
+
+impl Bundle {
+    /// Creates a new empty [Bundle].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a [Bundle] containing the changes applied to a [Repository]
+    /// and the contents they use.
+    ///
+    /// If `since` is `None`, all the applied changes are collected. Otherwise
+    /// the given change and the applied changes it replaces, directly or not,
+    /// are left out, as they are expected to already be known by the
+    /// receiver of the bundle.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if `since` is not applied to the repository,
+    /// if a used content is not found in the registry or if there was an
+    /// error while reading the repository or the registry.
+    pub fn collect<'manager, Repo: Repository<'manager>, Reg: Registry>(
+        repository: &Repo,
+        registry: &Reg,
+        since: Option<ChangeHash>,
+    ) -> Result<Self, BundleError<Repo::Error, Reg::Error>> {
+        let history = repository.history().map_err(BundleError::Repository)?;
+
+        // Find the changes that are known by the receiver
+        let mut known = HashSet::new();
+        if let Some(since) = since {
+            let changes = history.iter().copied().collect::<HashMap<_, _>>();
+            if !changes.contains_key(&since) {
+                return Err(BundleError::ChangeNotFound(since));
+            }
+            let mut to_visit = vec![since];
+            while let Some(change_hash) = to_visit.pop() {
+                if known.insert(change_hash) {
+                    if let Some(change) = changes.get(&change_hash) {
+                        to_visit.extend(change.replace);
+                    }
+                }
+            }
+        }
+
+        // Collect the other changes along with their contents
+        let mut bundle = Self::new();
+        for (change_hash, change) in history {
+            if known.contains(&change_hash) {
+                continue;
+            }
+            if let ChangeContent::LineContent { content, .. } = change.content {
+                if let Entry::Vacant(entry) = bundle.contents.entry(content) {
+                    let mut reader = registry
+                        .read(content)
+                        .map_err(BundleError::Registry)?
+                        .ok_or(BundleError::NotFound(content))?;
+                    let mut buffer = Vec::new();
+                    reader.read_to_end(&mut buffer)?;
+                    entry.insert(buffer);
+                }
+            }
+            bundle.add_change(change);
+        }
+        Ok(bundle)
+    }
+
+    /// Adds a [Change] to the bundle.
+    pub fn add_change(&mut self, change: Change) {
+        self.changes.push(change);
+    }
+
+    /// Adds a content to the bundle and returns its hash.
+    pub fn add_content(&mut self, content: Vec<u8>) -> ContentHash {
+        let hash = ContentHash::new(Sha256::digest(&content).into());
+        self.contents.entry(hash).or_insert(content);
+        hash
+    }
+
+    /// Returns the changes of the bundle.
+    #[must_use]
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Returns an [Iterator] over the contents of the bundle and their hashes,
+    /// sorted by hash.
+    pub fn contents(&self) -> impl Iterator<Item = (ContentHash, &[u8])> {
+        self.contents
+            .iter()
+            .map(|(hash, content)| (*hash, content.as_slice()))
+    }
+
+    /// Writes the contents of the bundle to a [Registry] and applies its
+    /// changes to a [Repository] with [`Repository::apply_bundle`].
+    ///
+    /// Nothing is written if a content used by a change is neither in the
+    /// bundle nor in the registry.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if a used content is missing or if there was
+    /// an error while writing to the registry or applying the changes. In
+    /// the latter case the repository should be dropped without being
+    /// committed.
+    pub fn apply<'manager, Repo: Repository<'manager>, Reg: Registry>(
+        &self,
+        repository: &mut Repo,
+        registry: &Reg,
+    ) -> Result<Vec<ChangeHash>, BundleError<Repo::Error, Reg::Error>> {
+        for change in &self.changes {
+            if let ChangeContent::LineContent { content, .. } = change.content {
+                if !self.contents.contains_key(&content)
+                    && !registry.exists(content).map_err(BundleError::Registry)?
+                {
+                    return Err(BundleError::NotFound(content));
+                }
+            }
+        }
+        for content in self.contents.values() {
+            registry
+                .write(content.as_slice())
+                .map_err(BundleError::Registry)?;
+        }
+        repository
+            .apply_bundle(&self.changes)
+            .map_err(BundleError::Repository)
+    }
+
+    /// Writes the bundle into a [Write].
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if there was an error while writing.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        borsh::to_writer(&mut data, self)?;
+        let checksum = Sha256::digest(&data);
+        writer.write_all(&data)?;
+        writer.write_all(&checksum)
+    }
+
+    /// Reads a bundle from a [Read] and checks its integrity.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the data is not a valid bundle, if it is
+    /// corrupted or if there was an error while reading.
+    pub fn read(mut reader: impl Read) -> Result<Self, BundleReadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        // Check the header and the checksum
+        let payload = data
+            .strip_prefix(MAGIC.as_slice())
+            .ok_or(BundleReadError::NotABundle)?;
+        let (&version, payload) = payload.split_first().ok_or(BundleReadError::NotABundle)?;
+        if version != VERSION {
+            return Err(BundleReadError::UnsupportedVersion(version));
+        }
+        let (payload, checksum) = payload
+            .split_last_chunk::<32>()
+            .ok_or(BundleReadError::Corrupted)?;
+        let data = data
+            .get(..data.len().saturating_sub(checksum.len()))
+            .ok_or(BundleReadError::Corrupted)?;
+        if Sha256::digest(data).as_slice() != checksum {
+            return Err(BundleReadError::Corrupted);
+        }
+
+        // Check that the contents match their hashes
+        let bundle: Self = borsh::from_slice(payload)?;
+        for (hash, content) in &bundle.contents {
+            if Sha256::digest(content).as_slice() != hash.as_bytes() {
+                return Err(BundleReadError::InvalidContent(*hash));
+            }
+        }
+        Ok(bundle)
+    }
+}
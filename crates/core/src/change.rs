@@ -8,6 +8,7 @@ use base64::prelude::*;
 use borsh::{BorshDeserialize, BorshSerialize};
 use sha2::{Digest, Sha256};
 use solipr_stack::StackVec;
+use thiserror::Error;
 use uuid::Uuid;
 
 use crate::registry::ContentHash;
@@ -17,7 +18,6 @@ use crate::registry::ContentHash;
 pub struct ChangeHash([u8; 32]);
 
 impl Debug for ChangeHash {
-    #[expect(clippy::min_ident_chars, reason = "the trait is made that way")]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_tuple("ChangeHash")
             .field(&format_args!("{}", BASE64_URL_SAFE_NO_PAD.encode(self.0)))
@@ -26,7 +26,6 @@ impl Debug for ChangeHash {
 }
 
 impl Display for ChangeHash {
-    #[expect(clippy::min_ident_chars, reason = "the trait is made that way")]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "change:{}", BASE64_URL_SAFE_NO_PAD.encode(self.0))
     }
@@ -51,7 +50,6 @@ impl FromStr for ChangeHash {
 pub struct FileId(Uuid);
 
 impl Display for FileId {
-    #[expect(clippy::min_ident_chars, reason = "the trait is made that way")]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "file:{}", self.0)
     }
@@ -111,7 +109,6 @@ impl LineId {
 }
 
 impl Display for LineId {
-    #[expect(clippy::min_ident_chars, reason = "the trait is made that way")]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "line:{}", self.0)
     }
@@ -156,6 +153,23 @@ pub enum SingleId {
     LineParent(FileId, LineId),
 }
 
+/// An error returned when a [Change] is not valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum InvalidChange {
+    /// The change replaces itself.
+    #[error("the change {0} replaces itself")]
+    SelfReplacement(ChangeHash),
+
+    /// The change replaces the same change multiple times.
+    #[error("the change {0} is replaced multiple times")]
+    DuplicateReplacement(ChangeHash),
+
+    /// The change replaces, or is replaced by, a change that modifies another
+    /// SVG.
+    #[error("the change {0} modifies another SVG")]
+    ForeignReplacement(ChangeHash),
+}
+
 /// A change that can be applied to a repository.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, BorshDeserialize, BorshSerialize)]
 pub struct Change {
@@ -180,6 +194,12 @@ impl Change {
         self.content.single_id()
     }
 
+    /// The file modified by this change.
+    #[must_use]
+    pub const fn file_id(&self) -> FileId {
+        self.content.file_id()
+    }
+
     /// Returns the hash of this change.
     #[must_use]
     pub fn calculate_hash(&self) -> ChangeHash {
@@ -188,6 +208,33 @@ impl Change {
         borsh::to_writer(&mut hasher, self).ok();
         ChangeHash(hasher.finalize().into())
     }
+
+    /// Checks that this change is structurally valid.
+    ///
+    /// This does not check that the replaced changes exist or that they
+    /// modify the same SVG, this must be done by the repository.
+    ///
+    /// # Errors
+    ///
+    /// An [`InvalidChange`] will be returned if the change replaces itself or
+    /// replaces the same change multiple times.
+    pub fn validate(&self) -> Result<(), InvalidChange> {
+        let change_hash = self.calculate_hash();
+        for (index, replaced_hash) in self.replace.iter().enumerate() {
+            if replaced_hash == change_hash {
+                return Err(InvalidChange::SelfReplacement(change_hash));
+            }
+            if self
+                .replace
+                .iter()
+                .skip(index.saturating_add(1))
+                .any(|hash| hash == replaced_hash)
+            {
+                return Err(InvalidChange::DuplicateReplacement(replaced_hash));
+            }
+        }
+        Ok(())
+    }
 }
 
 /// The content of a [Change].
@@ -281,4 +328,15 @@ impl ChangeContent {
             } => SingleId::LineChild(file_id, line_id),
         }
     }
+
+    /// Returns the file modified by this [`ChangeContent`].
+    #[must_use]
+    pub const fn file_id(&self) -> FileId {
+        match *self {
+            Self::LineExistence { file_id, .. }
+            | Self::LineContent { file_id, .. }
+            | Self::LineParent { file_id, .. }
+            | Self::LineChild { file_id, .. } => file_id,
+        }
+    }
 }
@@ -1,7 +1,6 @@
 //! A rust-based version control system
 
-#![feature(stmt_expr_attributes)]
-
+pub mod bundle;
 pub mod change;
 pub mod registry;
 pub mod repository;
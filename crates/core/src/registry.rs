@@ -1,13 +1,20 @@
 //! A registry that can be used so store and retrieve bytes arrays of any
 //! length.
 
+use std::collections::HashSet;
 use std::error::Error;
 use std::fmt::{self, Debug, Display};
-use std::io::Read;
+#[cfg(feature = "tokio")]
+use std::future::Future;
+use std::io::{self, Read};
 use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
 use base64::prelude::*;
 use borsh::{BorshDeserialize, BorshSerialize};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncRead;
 
 /// The hash of a content stored in the registry.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, BorshDeserialize, BorshSerialize)]
@@ -28,7 +35,6 @@ impl ContentHash {
 }
 
 impl Debug for ContentHash {
-    #[expect(clippy::min_ident_chars, reason = "the trait is made that way")]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_tuple("ContentHash")
             .field(&format_args!("{}", BASE64_URL_SAFE_NO_PAD.encode(self.0)))
@@ -37,7 +43,6 @@ impl Debug for ContentHash {
 }
 
 impl Display for ContentHash {
-    #[expect(clippy::min_ident_chars, reason = "the trait is made that way")]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "content:{}", BASE64_URL_SAFE_NO_PAD.encode(self.0))
     }
@@ -55,6 +60,33 @@ impl FromStr for ContentHash {
     }
 }
 
+/// The minimum number of characters of a [`ContentHash`] prefix that can be
+/// resolved by [`Registry::resolve_content`].
+pub const MIN_CONTENT_PREFIX_LENGTH: usize = 6;
+
+/// An error that can happen while resolving a [`ContentHash`] prefix.
+#[derive(Debug, thiserror::Error)]
+pub enum ResolveContentError<E> {
+    /// An error that can happen while reading the registry.
+    #[error("registry error: {0}")]
+    Registry(E),
+
+    /// The prefix is too short to be resolved.
+    #[error("the prefix {0:?} is shorter than {MIN_CONTENT_PREFIX_LENGTH} characters")]
+    TooShort(String),
+
+    /// No content in the registry matches the prefix.
+    #[error("no content matches the prefix {0:?}")]
+    NotFound(String),
+
+    /// Multiple contents in the registry match the prefix.
+    #[error(
+        "ambiguous prefix {0:?}, candidates: {}",
+        .1.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    Ambiguous(String, Vec<ContentHash>),
+}
+
 /// A registry that can be used to store and retrieve byte arrays of any length.
 pub trait Registry {
     /// The error that can be returned when doing a registry operation.
@@ -79,4 +111,289 @@ pub trait Registry {
     ///
     /// An error will be returned if the content could not be written.
     fn write(&self, content: impl Read) -> Result<ContentHash, Self::Error>;
+
+    /// Returns the size in bytes of the content with the given hash.
+    ///
+    /// Returns `None` if the content is not found.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the size could not be read.
+    fn size(&self, hash: ContentHash) -> Result<Option<u64>, Self::Error>;
+
+    /// Returns the last time the content with the given hash was written.
+    ///
+    /// Returns `None` if the content is not found.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the time could not be read.
+    fn written_at(&self, hash: ContentHash) -> Result<Option<SystemTime>, Self::Error>;
+
+    /// Returns `true` if the content with the given hash is in the registry.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the registry could not be read.
+    fn exists(&self, hash: ContentHash) -> Result<bool, Self::Error> {
+        Ok(self.size(hash)?.is_some())
+    }
+
+    /// Writes the given data into the registry and checks that its hash is
+    /// the expected one.
+    ///
+    /// This should be used for contents that are not trusted, such as the
+    /// ones received from the network. Implementations should override it so
+    /// that a content that does not have the expected hash is never stored.
+    /// The default implementation can only check the hash after writing, so
+    /// the content is then left in the registry, where
+    /// [`Registry::collect_garbage`] will remove it if it is unused.
+    ///
+    /// # Errors
+    ///
+    /// An error of kind [`io::ErrorKind::InvalidData`] will be returned if the
+    /// hash of the content is not the expected one, and other errors will be
+    /// returned if the content could not be written.
+    fn write_verified(&self, content: impl Read, expected: ContentHash) -> Result<(), Self::Error>
+    where
+        Self::Error: From<io::Error>,
+    {
+        let hash = self.write(content)?;
+        if hash != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the content has the hash {hash} instead of {expected}"),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Checks that the content with the given hash still matches its hash.
+    ///
+    /// Returns `None` if the content is not found.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the content could not be read.
+    fn verify(&self, hash: ContentHash) -> Result<Option<bool>, Self::Error>
+    where
+        Self::Error: From<io::Error>,
+    {
+        let Some(mut content) = self.read(hash)? else {
+            return Ok(None);
+        };
+        let mut hasher = Sha256::new();
+        io::copy(&mut content, &mut hasher)?;
+        Ok(Some(hasher.finalize().as_slice() == hash.as_bytes()))
+    }
+
+    /// Checks all the contents of the registry and returns the hashes of the
+    /// ones that do not match their hash anymore.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the contents could not be listed or read.
+    fn verify_all(&self) -> Result<Vec<ContentHash>, Self::Error>
+    where
+        Self::Error: From<io::Error>,
+    {
+        let mut corrupted = Vec::new();
+        for hash in self.contents()? {
+            if self.verify(hash)? == Some(false) {
+                corrupted.push(hash);
+            }
+        }
+        Ok(corrupted)
+    }
+
+    /// Returns the hashes of all the contents stored in the registry.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the contents could not be listed.
+    fn contents(&self) -> Result<Vec<ContentHash>, Self::Error>;
+
+    /// Returns the hash of the only content of the registry whose hash starts
+    /// with the given prefix.
+    ///
+    /// The prefix can optionally start with `content:` and must be at least
+    /// [`MIN_CONTENT_PREFIX_LENGTH`] characters long.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the prefix is too short, if it does not
+    /// match exactly one content or if the contents could not be listed.
+    fn resolve_content(
+        &self,
+        prefix: &str,
+    ) -> Result<ContentHash, ResolveContentError<Self::Error>> {
+        let prefix = prefix.trim();
+        let prefix = prefix.strip_prefix("content:").unwrap_or(prefix);
+        if prefix.chars().count() < MIN_CONTENT_PREFIX_LENGTH {
+            return Err(ResolveContentError::TooShort(prefix.to_owned()));
+        }
+
+        // Find all the contents matching the prefix
+        let needle = format!("content:{prefix}");
+        let mut candidates = self
+            .contents()
+            .map_err(ResolveContentError::Registry)?
+            .into_iter()
+            .filter(|hash| hash.to_string().starts_with(&needle))
+            .collect::<Vec<_>>();
+
+        match candidates.as_slice() {
+            [] => Err(ResolveContentError::NotFound(prefix.to_owned())),
+            &[hash] => Ok(hash),
+            _ => {
+                candidates.sort_unstable();
+                Err(ResolveContentError::Ambiguous(
+                    prefix.to_owned(),
+                    candidates,
+                ))
+            }
+        }
+    }
+
+    /// Removes the content with the given hash from the registry.
+    ///
+    /// Returns `false` if the content was not found.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the content could not be removed.
+    fn remove(&self, hash: ContentHash) -> Result<bool, Self::Error>;
+
+    /// Removes the content with the given hash from the registry if it was
+    /// not written after `time`.
+    ///
+    /// Returns `false` if the content was not found or was written after
+    /// `time`.
+    ///
+    /// The default implementation checks [`Registry::written_at`] right
+    /// before calling [`Registry::remove`]. Implementations should override it
+    /// so that a content written again between the two is never removed.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the content could not be removed.
+    fn remove_if_written_before(
+        &self,
+        hash: ContentHash,
+        time: SystemTime,
+    ) -> Result<bool, Self::Error> {
+        match self.written_at(hash)? {
+            Some(written_at) if written_at <= time => self.remove(hash),
+            Some(_) | None => Ok(false),
+        }
+    }
+
+    /// Removes all the contents of the registry that are not in `used` and
+    /// were last written more than `grace_period` ago, and returns their
+    /// hashes.
+    ///
+    /// If `dry_run` is `true`, the contents are only returned and nothing is
+    /// removed.
+    ///
+    /// A content is usually written before the change using it is committed,
+    /// so the grace period must be longer than the time between the two.
+    /// `used` must contain the contents of every repository sharing this
+    /// registry, which is what the `collect_garbage` method of
+    /// [`RepositoryManager`](crate::repository::RepositoryManager) passes.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the contents could not be listed or
+    /// removed.
+    fn collect_garbage(
+        &self,
+        used: &HashSet<ContentHash>,
+        grace_period: Duration,
+        dry_run: bool,
+    ) -> Result<Vec<ContentHash>, Self::Error> {
+        // Each content is only removed if it was not written again since it
+        // was listed, so that a content written concurrently is always kept
+        let Some(written_before) = SystemTime::now().checked_sub(grace_period) else {
+            return Ok(Vec::new());
+        };
+        let mut unused = Vec::new();
+        for hash in self.contents()? {
+            if used.contains(&hash) {
+                continue;
+            }
+            let removed = if dry_run {
+                self.written_at(hash)?
+                    .is_some_and(|written_at| written_at <= written_before)
+            } else {
+                self.remove_if_written_before(hash, written_before)?
+            };
+            if removed {
+                unused.push(hash);
+            }
+        }
+        Ok(unused)
+    }
+}
+
+/// An asynchronous version of [Registry].
+///
+/// It should be used instead of [Registry] in asynchronous contexts so that
+/// reading and writing large contents does not block the runtime. It is only
+/// available with the `tokio` feature, so that the other users of this crate
+/// do not depend on an async runtime.
+#[cfg(feature = "tokio")]
+pub trait AsyncRegistry {
+    /// The error that can be returned when doing a registry operation.
+    type Error: Error;
+
+    /// Returns an [`AsyncRead`] to the content with the given hash.
+    ///
+    /// Returns `None` if the content is not found.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the content could not be read.
+    fn read(
+        &self,
+        hash: ContentHash,
+    ) -> impl Future<Output = Result<Option<impl AsyncRead + Unpin + Send>, Self::Error>> + Send;
+
+    /// Writes the given data into the registry and returns the hash of the
+    /// written content.
+    ///
+    /// If the content already exists, nothing will happen and the
+    /// [`ContentHash`] will still be returned.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the content could not be written.
+    fn write(
+        &self,
+        content: impl AsyncRead + Unpin + Send,
+    ) -> impl Future<Output = Result<ContentHash, Self::Error>> + Send;
+
+    /// Returns the size in bytes of the content with the given hash.
+    ///
+    /// Returns `None` if the content is not found.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the size could not be read.
+    fn size(
+        &self,
+        hash: ContentHash,
+    ) -> impl Future<Output = Result<Option<u64>, Self::Error>> + Send;
+
+    /// Returns `true` if the content with the given hash is in the registry.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the registry could not be read.
+    fn exists(&self, hash: ContentHash) -> impl Future<Output = Result<bool, Self::Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move { Ok(self.size(hash).await?.is_some()) }
+    }
 }
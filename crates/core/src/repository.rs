@@ -3,16 +3,19 @@
 //! These traits are used to open repositories, apply changes to them and
 //! retrieve information from them.
 
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{self, Display};
 use std::ops::Deref;
 use std::str::FromStr;
+use std::time::Duration;
 
 use borsh::{BorshDeserialize, BorshSerialize};
+use thiserror::Error;
 use uuid::Uuid;
 
-use crate::change::{Change, ChangeHash, FileId, LineId, SingleId};
+use crate::change::{Change, ChangeContent, ChangeHash, FileId, LineId, SingleId};
+use crate::registry::{ContentHash, Registry};
 
 pub mod diff;
 pub mod graph;
@@ -34,7 +37,6 @@ impl RepositoryId {
 }
 
 impl Display for RepositoryId {
-    #[expect(clippy::min_ident_chars, reason = "the trait is made that way")]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "repo:{}", self.0)
     }
@@ -58,6 +60,46 @@ impl Deref for RepositoryId {
     }
 }
 
+/// The minimum number of characters of a [`ChangeHash`] prefix that can be
+/// resolved by [`Repository::resolve_change`].
+pub const MIN_CHANGE_PREFIX_LENGTH: usize = 6;
+
+/// An error that can happen while resolving a [`ChangeHash`] prefix.
+#[derive(Debug, Error)]
+pub enum ResolveChangeError<E> {
+    /// An error that can happen while reading the repository.
+    #[error("repository error: {0}")]
+    Repository(E),
+
+    /// The prefix is too short to be resolved.
+    #[error("the prefix {0:?} is shorter than {MIN_CHANGE_PREFIX_LENGTH} characters")]
+    TooShort(String),
+
+    /// No change in the repository matches the prefix.
+    #[error("no change matches the prefix {0:?}")]
+    NotFound(String),
+
+    /// Multiple changes in the repository match the prefix.
+    #[error(
+        "ambiguous prefix {0:?}, candidates: {}",
+        .1.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    Ambiguous(String, Vec<ChangeHash>),
+}
+
+/// An error that can happen while collecting the garbage of a [Registry]
+/// shared by the repositories of a [`RepositoryManager`].
+#[derive(Debug, Error)]
+pub enum CollectGarbageError<ManagerError, RegError> {
+    /// An error that can happen while reading the repositories.
+    #[error("repository error: {0}")]
+    Repository(ManagerError),
+
+    /// An error that can happen while using the registry.
+    #[error("registry error: {0}")]
+    Registry(RegError),
+}
+
 /// A [Repository] manager, used to open repositories.
 pub trait RepositoryManager {
     /// The error that can be returned when opening a repository.
@@ -85,6 +127,39 @@ pub trait RepositoryManager {
     ///
     /// An error will be returned if the repository could not be opened.
     fn open_write(&self, repository_id: RepositoryId) -> Result<Self::Repository<'_>, Self::Error>;
+
+    /// Returns the hashes of the contents used by the changes applied to any
+    /// repository of the manager.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the repositories could not be read.
+    fn used_contents(&self) -> Result<HashSet<ContentHash>, Self::Error>;
+
+    /// Removes the contents of the given [Registry] that are not used by any
+    /// repository of the manager and were last written more than
+    /// `grace_period` ago, and returns their hashes.
+    ///
+    /// The registry must only be used by the repositories of this manager.
+    /// See [`Registry::collect_garbage`] for the meaning of the arguments.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the repositories could not be read or if
+    /// the registry could not be cleaned.
+    fn collect_garbage<R: Registry>(
+        &self,
+        registry: &R,
+        grace_period: Duration,
+        dry_run: bool,
+    ) -> Result<Vec<ContentHash>, CollectGarbageError<Self::Error, R::Error>> {
+        let used = self
+            .used_contents()
+            .map_err(CollectGarbageError::Repository)?;
+        registry
+            .collect_garbage(&used, grace_period, dry_run)
+            .map_err(CollectGarbageError::Registry)
+    }
 }
 
 /// A repository.
@@ -110,6 +185,77 @@ pub trait Repository<'manager> {
     /// operation.
     fn change(&self, change_hash: ChangeHash) -> Result<Option<Change>, Self::Error>;
 
+    /// Returns the hash of the only [Change] of the repository whose hash
+    /// starts with the given prefix.
+    ///
+    /// The prefix can optionally start with `change:` and must be at least
+    /// [`MIN_CHANGE_PREFIX_LENGTH`] characters long.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the prefix is too short, if it does not
+    /// match exactly one change or if there was an error while reading the
+    /// repository.
+    fn resolve_change(&self, prefix: &str) -> Result<ChangeHash, ResolveChangeError<Self::Error>> {
+        let prefix = prefix.trim();
+        let prefix = prefix.strip_prefix("change:").unwrap_or(prefix);
+        if prefix.chars().count() < MIN_CHANGE_PREFIX_LENGTH {
+            return Err(ResolveChangeError::TooShort(prefix.to_owned()));
+        }
+
+        // Find all the changes matching the prefix
+        let needle = format!("change:{prefix}");
+        let mut candidates = Vec::new();
+        for change in self.changes() {
+            let (change_hash, _) = change.map_err(ResolveChangeError::Repository)?;
+            if change_hash.to_string().starts_with(&needle) {
+                candidates.push(change_hash);
+            }
+        }
+
+        match candidates.as_slice() {
+            [] => Err(ResolveChangeError::NotFound(prefix.to_owned())),
+            &[change_hash] => Ok(change_hash),
+            _ => {
+                candidates.sort_unstable();
+                Err(ResolveChangeError::Ambiguous(prefix.to_owned(), candidates))
+            }
+        }
+    }
+
+    /// Returns all the [Change]s applied to the repository in a deterministic
+    /// topological order.
+    ///
+    /// A change always comes after the applied changes it replaces, and
+    /// changes that are not ordered this way are sorted by hash.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if there was an error while doing the
+    /// operation.
+    fn history(&self) -> Result<Vec<(ChangeHash, Change)>, Self::Error> {
+        let changes = self.changes().collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(topological_order(changes))
+    }
+
+    /// Returns the hashes of the registry contents used by the [Change]s
+    /// applied to the repository.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if there was an error while doing the
+    /// operation.
+    fn used_contents(&self) -> Result<HashSet<ContentHash>, Self::Error> {
+        let mut used = HashSet::new();
+        for change in self.changes() {
+            let (_, change) = change?;
+            if let ChangeContent::LineContent { content, .. } = change.content {
+                used.insert(content);
+            }
+        }
+        Ok(used)
+    }
+
     /// Returns the heads of the given [`SingleId`].
     ///
     /// # Errors
@@ -132,6 +278,15 @@ pub trait Repository<'manager> {
     /// operation.
     fn existing_lines(&self, file_id: FileId) -> Result<HashSet<LineId>, Self::Error>;
 
+    /// Returns the files modified by at least one [Change] applied to the
+    /// [Repository].
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if there was an error while doing the
+    /// operation.
+    fn files(&self) -> Result<HashSet<FileId>, Self::Error>;
+
     /// Applies the given [`Change`] to the repository and returns the hash of
     /// the applied change.
     ///
@@ -144,6 +299,28 @@ pub trait Repository<'manager> {
     /// operation.
     fn apply(&mut self, change: Change) -> Result<ChangeHash, Self::Error>;
 
+    /// Applies all the given [Change]s to the repository and returns their
+    /// hashes in the order they were applied.
+    ///
+    /// The changes are applied in a deterministic topological order, so a
+    /// change is always applied after the changes of the bundle it replaces.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if one of the changes could not be applied.
+    /// In this case the repository should be dropped without being
+    /// committed to discard the changes of the bundle that were applied.
+    fn apply_bundle(&mut self, changes: &[Change]) -> Result<Vec<ChangeHash>, Self::Error> {
+        let changes = changes
+            .iter()
+            .map(|&change| (change.calculate_hash(), change))
+            .collect();
+        topological_order(changes)
+            .into_iter()
+            .map(|(_, change)| self.apply(change))
+            .collect()
+    }
+
     /// Unapplies the change with the given [`ChangeHash`].
     ///
     /// If the change is not applied, `Ok(())` will be returned and nothing
@@ -163,3 +340,45 @@ pub trait Repository<'manager> {
     /// operation.
     fn commit(self) -> Result<(), Self::Error>;
 }
+
+/// Sorts the given [Change]s so that every change comes after the changes it
+/// replaces, breaking ties by hash.
+fn topological_order(mut changes: HashMap<ChangeHash, Change>) -> Vec<(ChangeHash, Change)> {
+    // Find the given changes replaced by each change
+    let mut dependencies = HashMap::<ChangeHash, HashSet<ChangeHash>>::new();
+    let mut dependents = HashMap::<ChangeHash, Vec<ChangeHash>>::new();
+    for (&change_hash, change) in &changes {
+        for replaced in &change.replace {
+            if changes.contains_key(&replaced) {
+                dependencies
+                    .entry(change_hash)
+                    .or_default()
+                    .insert(replaced);
+                dependents.entry(replaced).or_default().push(change_hash);
+            }
+        }
+    }
+
+    // Output the changes once all their dependencies have been output
+    let mut ready = changes
+        .keys()
+        .filter(|change_hash| !dependencies.contains_key(change_hash))
+        .copied()
+        .collect::<BTreeSet<_>>();
+    let mut ordered = Vec::with_capacity(changes.len());
+    while let Some(change_hash) = ready.pop_first() {
+        if let Some(change) = changes.remove(&change_hash) {
+            ordered.push((change_hash, change));
+        }
+        for dependent in dependents.remove(&change_hash).unwrap_or_default() {
+            if let Some(remaining) = dependencies.get_mut(&dependent) {
+                remaining.remove(&change_hash);
+                if remaining.is_empty() {
+                    dependencies.remove(&dependent);
+                    ready.insert(dependent);
+                }
+            }
+        }
+    }
+    ordered
+}
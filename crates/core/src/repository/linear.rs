@@ -119,14 +119,19 @@ impl LinearFile {
 
         // Parse all lines
         let mut reader = BufReader::new(reader);
-        let mut line = vec![1];
+        let mut line = Vec::new();
+        let mut last_line = false;
         let mut conflict = None;
         let mut cycle = None;
-        while !line.is_empty() {
-            // Read the next line
+        while !last_line {
+            // Read the next line, the last one is the only one without a
+            // trailing newline and it is empty if the file ends with one
             line.clear();
             reader.read_until(b'\n', &mut line)?;
-            let content = line.strip_suffix(b"\n").unwrap_or(&line);
+            let content = line.strip_suffix(b"\n").unwrap_or_else(|| {
+                last_line = true;
+                &line
+            });
 
             // Check if we have a conflict
             if let Some(id) = content.strip_prefix(b"<<<<<<< CONFLICT ") {
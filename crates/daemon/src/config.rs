@@ -0,0 +1,278 @@
+//! Defines the configuration of the Solipr daemon and its validation.
+
+use core::fmt::{self, Display};
+use std::path::{Component, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// The maximum length in bytes of the path of a Unix socket.
+///
+/// The path is stored in a fixed size buffer of 108 bytes on Linux, and this
+/// buffer must end with a null byte.
+const MAX_SOCKET_PATH_LENGTH: usize = 107;
+
+/// The configuration of the Solipr daemon.
+#[derive(Deserialize, Serialize)]
+pub struct Config {
+    /// The path of the folder in which Solipr stores its data.
+    pub data_folder: PathBuf,
+
+    /// The path of the daemon's socket.
+    ///
+    /// This path is relative to the Solipr data folder.
+    pub socket_path: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let home = home::home_dir().unwrap_or_default();
+        Self {
+            data_folder: home.join(".solipr"),
+            socket_path: PathBuf::from("solipr.sock"),
+        }
+    }
+}
+
+impl Config {
+    /// Returns the full path of the daemon's socket.
+    pub fn socket_path(&self) -> PathBuf {
+        self.data_folder.join(&self.socket_path)
+    }
+
+    /// Checks the configuration and returns all the issues found in it.
+    ///
+    /// The daemon should not start if one of the issues is an
+    /// [`Severity::Error`].
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+
+        // Check the data folder
+        if self.data_folder.as_os_str().is_empty() {
+            issues.push(ConfigIssue {
+                severity: Severity::Error,
+                field: "data_folder",
+                value: String::new(),
+                message: "the data folder is empty",
+                suggestion: "set it to an absolute path such as \"~/.solipr\"",
+            });
+        } else if self.data_folder.is_relative() {
+            issues.push(ConfigIssue {
+                severity: Severity::Warning,
+                field: "data_folder",
+                value: self.data_folder.display().to_string(),
+                message: "the data folder is relative to the directory the daemon is started from",
+                suggestion: "use an absolute path",
+            });
+        }
+        if self.data_folder.exists() && !self.data_folder.is_dir() {
+            issues.push(ConfigIssue {
+                severity: Severity::Error,
+                field: "data_folder",
+                value: self.data_folder.display().to_string(),
+                message: "the data folder is not a directory",
+                suggestion: "remove the file or choose another path",
+            });
+        }
+
+        // Check the socket path
+        if self.socket_path.as_os_str().is_empty() {
+            issues.push(ConfigIssue {
+                severity: Severity::Error,
+                field: "socket_path",
+                value: String::new(),
+                message: "the socket path is empty",
+                suggestion: "set it to a file name such as \"solipr.sock\"",
+            });
+        } else if self.socket_path.is_absolute() {
+            issues.push(ConfigIssue {
+                severity: Severity::Warning,
+                field: "socket_path",
+                value: self.socket_path.display().to_string(),
+                message: "the socket path is absolute so the data folder is ignored for it",
+                suggestion: "use a path relative to the data folder",
+            });
+        }
+        if self
+            .socket_path
+            .components()
+            .any(|component| component == Component::ParentDir)
+        {
+            issues.push(ConfigIssue {
+                severity: Severity::Error,
+                field: "socket_path",
+                value: self.socket_path.display().to_string(),
+                message: "the socket path can leave the folder in which it is created",
+                suggestion: "remove the \"..\" components from the path",
+            });
+        }
+        if self.socket_path().as_os_str().len() > MAX_SOCKET_PATH_LENGTH {
+            issues.push(ConfigIssue {
+                severity: Severity::Error,
+                field: "socket_path",
+                value: self.socket_path().display().to_string(),
+                message: "the full socket path is too long for a Unix socket",
+                suggestion: "use a shorter socket path or data folder",
+            });
+        }
+
+        issues
+    }
+}
+
+/// How serious a [`ConfigIssue`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The daemon can start but may not behave as expected.
+    Warning,
+
+    /// The daemon cannot start with this configuration.
+    Error,
+}
+
+/// An issue found while validating a [`Config`].
+#[derive(Debug, Clone)]
+pub struct ConfigIssue {
+    /// How serious the issue is.
+    pub severity: Severity,
+
+    /// The name of the field that has the issue.
+    pub field: &'static str,
+
+    /// The offending value.
+    pub value: String,
+
+    /// What is wrong with the value.
+    pub message: &'static str,
+
+    /// How to fix the issue.
+    pub suggestion: &'static str,
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{severity}: {} = {:?}: {} ({})",
+            self.field, self.value, self.message, self.suggestion
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    /// Returns the fields of the errors found in the given configuration.
+    fn errors(config: &Config) -> Vec<&'static str> {
+        config
+            .validate()
+            .into_iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .map(|issue| issue.field)
+            .collect()
+    }
+
+    /// Returns the fields of the warnings found in the given configuration.
+    fn warnings(config: &Config) -> Vec<&'static str> {
+        config
+            .validate()
+            .into_iter()
+            .filter(|issue| issue.severity == Severity::Warning)
+            .map(|issue| issue.field)
+            .collect()
+    }
+
+    /// Returns a valid configuration.
+    fn valid_config() -> Config {
+        Config {
+            data_folder: PathBuf::from("/home/user/.solipr"),
+            socket_path: PathBuf::from("solipr.sock"),
+        }
+    }
+
+    #[test]
+    fn valid() {
+        assert!(valid_config().validate().is_empty());
+    }
+
+    #[test]
+    fn empty_paths() {
+        let mut config = valid_config();
+        config.data_folder = PathBuf::new();
+        assert_eq!(errors(&config), ["data_folder"]);
+        assert!(warnings(&config).is_empty());
+
+        let mut config = valid_config();
+        config.socket_path = PathBuf::new();
+        assert_eq!(errors(&config), ["socket_path"]);
+        assert!(warnings(&config).is_empty());
+    }
+
+    #[test]
+    fn relative_data_folder() {
+        let mut config = valid_config();
+        config.data_folder = PathBuf::from(".solipr");
+        assert!(errors(&config).is_empty());
+        assert_eq!(warnings(&config), ["data_folder"]);
+    }
+
+    #[test]
+    fn absolute_socket_path() {
+        let mut config = valid_config();
+        config.socket_path = PathBuf::from("/run/solipr.sock");
+        assert!(errors(&config).is_empty());
+        assert_eq!(warnings(&config), ["socket_path"]);
+        assert_eq!(config.socket_path(), PathBuf::from("/run/solipr.sock"));
+    }
+
+    #[test]
+    fn too_long_socket_path() {
+        let mut config = valid_config();
+        config.socket_path = PathBuf::from("a".repeat(MAX_SOCKET_PATH_LENGTH));
+        assert_eq!(errors(&config), ["socket_path"]);
+
+        // The limit applies to the full path, including the data folder
+        let folder_length = config.data_folder.as_os_str().len();
+        config.socket_path = PathBuf::from("a".repeat(MAX_SOCKET_PATH_LENGTH - folder_length - 1));
+        assert_eq!(
+            config.socket_path().as_os_str().len(),
+            MAX_SOCKET_PATH_LENGTH
+        );
+        assert!(errors(&config).is_empty());
+    }
+
+    #[test]
+    fn data_folder_not_a_directory() -> io::Result<()> {
+        let folder = tempfile::TempDir::new()?;
+        let file = tempfile::NamedTempFile::new_in(folder.path())?;
+        let mut config = valid_config();
+        config.data_folder = file.path().to_path_buf();
+        assert_eq!(errors(&config), ["data_folder"]);
+
+        config.data_folder = folder.path().to_path_buf();
+        assert!(errors(&config).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn socket_path_parent_dir() {
+        let mut config = valid_config();
+        config.socket_path = PathBuf::from("../solipr.sock");
+        assert_eq!(errors(&config), ["socket_path"]);
+
+        config.socket_path = PathBuf::from("sockets/../../solipr.sock");
+        assert_eq!(errors(&config), ["socket_path"]);
+
+        config.socket_path = PathBuf::from("/run/../tmp/solipr.sock");
+        assert_eq!(errors(&config), ["socket_path"]);
+
+        config.socket_path = PathBuf::from("sockets/solipr.sock");
+        assert!(errors(&config).is_empty());
+    }
+}
@@ -3,41 +3,74 @@
 //! This daemon should run in the background and is responsible for managing
 //! repositories and connecting to peers in the Solipr network.
 
-use std::path::PathBuf;
+mod config;
+
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
 
-use serde::{Deserialize, Serialize};
 use tokio::net::UnixListener;
 use tokio::{fs, io};
 
-/// The configuration of the Solipr daemon.
-#[derive(Deserialize, Serialize)]
-struct Config {
-    /// The path of the folder in which Solipr stores its data.
-    data_folder: PathBuf,
+use crate::config::{Config, Severity};
 
-    /// The path of the daemon's socket.
-    ///
-    /// This path is relative to the Solipr data folder.
-    socket_path: PathBuf,
-}
+#[tokio::main]
+#[expect(
+    clippy::unwrap_in_result,
+    reason = "the tokio::main macro uses expect internally"
+)]
+async fn main() -> io::Result<()> {
+    let config: Config = confy::load("solipr", None).map_err(io::Error::other)?;
 
-impl Default for Config {
-    fn default() -> Self {
-        let home = home::home_dir().unwrap_or_default();
-        Self {
-            data_folder: home.join(".solipr"),
-            socket_path: PathBuf::from("solipr.sock"),
+    // Report every issue in the configuration before giving up
+    let issues = config.validate();
+    for issue in &issues {
+        eprintln!("{issue}");
+    }
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the configuration has {errors} error(s)"),
+        ));
+    }
+
+    // Only the user running the daemon can access its data folder, so the
+    // socket is never reachable by others, even before its permissions are set
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&config.data_folder)
+        .await?;
+    fs::set_permissions(&config.data_folder, Permissions::from_mode(0o700)).await?;
+    // The paths are resolved before being compared, as a path can be inside
+    // the data folder component-wise while pointing outside of it
+    let socket_path = config.socket_path();
+    if let Some(socket_folder) = socket_path.parent() {
+        let data_folder = fs::canonicalize(&config.data_folder).await?;
+        if !fs::canonicalize(socket_folder)
+            .await?
+            .starts_with(data_folder)
+            && fs::metadata(socket_folder).await?.permissions().mode() & 0o077 != 0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "the socket folder {} must only be accessible by its owner",
+                    socket_folder.display()
+                ),
+            ));
         }
     }
-}
 
-#[tokio::main]
-async fn main() -> io::Result<()> {
-    let config: Config = confy::load("solipr", None).map_err(io::Error::other)?;
-    fs::create_dir_all(&config.data_folder).await?;
     {
-        let _ = UnixListener::bind(config.data_folder.join(&config.socket_path))?;
+        let _listener = UnixListener::bind(&socket_path)?;
+
+        // Only the user running the daemon can connect to its socket
+        fs::set_permissions(&socket_path, Permissions::from_mode(0o600)).await?;
     }
-    fs::remove_file(config.data_folder.join(&config.socket_path)).await?;
+    fs::remove_file(&socket_path).await?;
     Ok(())
 }
@@ -2,3 +2,4 @@
 //! in memory.
 
 pub mod registry;
+pub mod repository;
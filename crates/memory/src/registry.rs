@@ -3,15 +3,28 @@
 use std::collections::HashMap;
 use std::io::{self, Cursor, Read};
 use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
 
 use sha2::{Digest, Sha256};
 use solipr_core::registry::{ContentHash, Registry};
 
+/// A content stored in a [`MemoryRegistry`].
+struct StoredContent {
+    /// The bytes of the content.
+    data: Arc<[u8]>,
+
+    /// The last time the content was written.
+    written_at: SystemTime,
+}
+
 /// A memory based [Registry].
 #[derive(Default)]
 pub struct MemoryRegistry {
     /// The contents stored in the registry.
-    contents: RwLock<HashMap<ContentHash, Arc<[u8]>>>,
+    contents: RwLock<HashMap<ContentHash, StoredContent>>,
+
+    /// The maximum size of a content in bytes, if any.
+    max_content_size: Option<u64>,
 }
 
 impl MemoryRegistry {
@@ -20,6 +33,73 @@ impl MemoryRegistry {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Limits the size of the contents that can be written in this registry.
+    ///
+    /// Writing a content bigger than `max_content_size` bytes will fail with
+    /// an [`io::ErrorKind::FileTooLarge`] error.
+    #[must_use]
+    pub const fn with_max_content_size(mut self, max_content_size: u64) -> Self {
+        self.max_content_size = Some(max_content_size);
+        self
+    }
+
+    /// Writes the given content into the registry and returns its hash.
+    ///
+    /// If an `expected` hash is given and the content does not have it, the
+    /// content is not stored.
+    fn write_content(
+        &self,
+        mut content: impl Read,
+        expected: Option<ContentHash>,
+    ) -> io::Result<ContentHash> {
+        // Read the content into memory without going over the maximum size
+        let mut buffer = Vec::new();
+        if let Some(max_content_size) = self.max_content_size {
+            content
+                .by_ref()
+                .take(max_content_size.saturating_add(1))
+                .read_to_end(&mut buffer)?;
+            if buffer.len() as u64 > max_content_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::FileTooLarge,
+                    format!(
+                        "the content is bigger than the maximum size of {max_content_size} bytes"
+                    ),
+                ));
+            }
+        } else {
+            content.read_to_end(&mut buffer)?;
+        }
+
+        // Create a unique hash for the content
+        let mut hasher = Sha256::new();
+        hasher.update(&buffer);
+        let hash = ContentHash::new(hasher.finalize().into());
+        if let Some(expected) = expected {
+            if hash != expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("the content has the hash {hash} instead of {expected}"),
+                ));
+            }
+        }
+
+        // Write the content into the registry
+        let Ok(mut data) = self.contents.write() else {
+            return Err(io::Error::other("failed to write content".to_owned()));
+        };
+        data.insert(
+            hash,
+            StoredContent {
+                data: buffer.into(),
+                written_at: SystemTime::now(),
+            },
+        );
+
+        // Return the hash of the content
+        Ok(hash)
+    }
 }
 
 impl Registry for MemoryRegistry {
@@ -32,26 +112,60 @@ impl Registry for MemoryRegistry {
         let Some(content) = data.get(&hash) else {
             return Ok(None);
         };
-        Ok(Some(Cursor::new(Arc::clone(content))))
+        Ok(Some(Cursor::new(Arc::clone(&content.data))))
     }
 
-    fn write(&self, mut content: impl Read) -> Result<ContentHash, Self::Error> {
-        // Read the content into memory
-        let mut buffer = Vec::new();
-        content.read_to_end(&mut buffer)?;
+    fn write(&self, content: impl Read) -> Result<ContentHash, Self::Error> {
+        self.write_content(content, None)
+    }
 
-        // Create a unique hash for the content
-        let mut hasher = Sha256::new();
-        hasher.update(&buffer);
-        let hash = hasher.finalize().into();
+    fn write_verified(&self, content: impl Read, expected: ContentHash) -> Result<(), Self::Error> {
+        self.write_content(content, Some(expected)).map(|_| ())
+    }
 
-        // Write the content into the registry
+    fn size(&self, hash: ContentHash) -> Result<Option<u64>, Self::Error> {
+        let Ok(data) = self.contents.read() else {
+            return Err(io::Error::other("failed to read content".to_owned()));
+        };
+        Ok(data.get(&hash).map(|content| content.data.len() as u64))
+    }
+
+    fn written_at(&self, hash: ContentHash) -> Result<Option<SystemTime>, Self::Error> {
+        let Ok(data) = self.contents.read() else {
+            return Err(io::Error::other("failed to read content".to_owned()));
+        };
+        Ok(data.get(&hash).map(|content| content.written_at))
+    }
+
+    fn contents(&self) -> Result<Vec<ContentHash>, Self::Error> {
+        let Ok(data) = self.contents.read() else {
+            return Err(io::Error::other("failed to read content".to_owned()));
+        };
+        Ok(data.keys().copied().collect())
+    }
+
+    fn remove(&self, hash: ContentHash) -> Result<bool, Self::Error> {
         let Ok(mut data) = self.contents.write() else {
             return Err(io::Error::other("failed to write content".to_owned()));
         };
-        data.insert(ContentHash::new(hash), buffer.into());
+        Ok(data.remove(&hash).is_some())
+    }
 
-        // Return the hash of the content
-        Ok(ContentHash::new(hash))
+    fn remove_if_written_before(
+        &self,
+        hash: ContentHash,
+        time: SystemTime,
+    ) -> Result<bool, Self::Error> {
+        let Ok(mut data) = self.contents.write() else {
+            return Err(io::Error::other("failed to write content".to_owned()));
+        };
+        if data
+            .get(&hash)
+            .is_some_and(|content| content.written_at <= time)
+        {
+            data.remove(&hash);
+            return Ok(true);
+        }
+        Ok(false)
     }
 }
@@ -0,0 +1,304 @@
+//! An implementation of the [`RepositoryManager`] and [Repository] traits that
+//! stores data in memory.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::sync::{Mutex, MutexGuard, RwLock};
+
+use solipr_core::change::{
+    Change, ChangeContent, ChangeHash, FileId, InvalidChange, LineId, SingleId,
+};
+use solipr_core::registry::ContentHash;
+use solipr_core::repository::head::HeadExt;
+use solipr_core::repository::{Repository, RepositoryId, RepositoryManager};
+
+/// The data of a single repository.
+#[derive(Clone, Default)]
+struct RepositoryData {
+    /// All the changes applied to the repository.
+    changes: BTreeMap<ChangeHash, Change>,
+
+    /// The changes replacing each change.
+    reverse_heads: HashMap<ChangeHash, HashSet<ChangeHash>>,
+
+    /// The heads of each single.
+    heads: HashMap<SingleId, HashSet<ChangeHash>>,
+
+    /// The existing lines of each file.
+    lines: HashMap<FileId, HashSet<LineId>>,
+
+    /// The number of applied changes modifying each file.
+    files: HashMap<FileId, usize>,
+}
+
+/// An implementation of the [`RepositoryManager`] that stores data in memory.
+///
+/// Like the persistent implementation, a repository opened for reading is a
+/// snapshot, and only one repository can be opened for writing at a time.
+#[derive(Default)]
+pub struct MemoryRepositoryManager {
+    /// The data of all the repositories.
+    repositories: RwLock<HashMap<RepositoryId, RepositoryData>>,
+
+    /// The lock held by the repository opened for writing.
+    writer: Mutex<()>,
+}
+
+impl MemoryRepositoryManager {
+    /// Creates a new empty [`MemoryRepositoryManager`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of the data of the given repository.
+    fn snapshot(&self, repository_id: RepositoryId) -> io::Result<RepositoryData> {
+        let Ok(repositories) = self.repositories.read() else {
+            return Err(io::Error::other("failed to read repositories".to_owned()));
+        };
+        Ok(repositories
+            .get(&repository_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+impl RepositoryManager for MemoryRepositoryManager {
+    type Error = io::Error;
+
+    type Repository<'manager>
+        = MemoryRepository<'manager>
+    where
+        Self: 'manager;
+
+    fn open_read(&self, repository_id: RepositoryId) -> Result<Self::Repository<'_>, Self::Error> {
+        Ok(MemoryRepository {
+            id: repository_id,
+            manager: self,
+            data: self.snapshot(repository_id)?,
+            writer: None,
+        })
+    }
+
+    fn open_write(&self, repository_id: RepositoryId) -> Result<Self::Repository<'_>, Self::Error> {
+        let Ok(writer) = self.writer.lock() else {
+            return Err(io::Error::other("failed to lock repositories".to_owned()));
+        };
+        Ok(MemoryRepository {
+            id: repository_id,
+            manager: self,
+            data: self.snapshot(repository_id)?,
+            writer: Some(writer),
+        })
+    }
+
+    fn used_contents(&self) -> Result<HashSet<ContentHash>, Self::Error> {
+        let Ok(repositories) = self.repositories.read() else {
+            return Err(io::Error::other("failed to read repositories".to_owned()));
+        };
+        Ok(repositories
+            .values()
+            .flat_map(|data| data.changes.values())
+            .filter_map(|change| match change.content {
+                ChangeContent::LineContent { content, .. } => Some(content),
+                ChangeContent::LineExistence { .. }
+                | ChangeContent::LineParent { .. }
+                | ChangeContent::LineChild { .. } => None,
+            })
+            .collect())
+    }
+}
+
+/// Converts an [`InvalidChange`] into an [`io::Error`].
+fn invalid_change(err: InvalidChange) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+/// An implementation of the [Repository] trait that stores data in memory.
+pub struct MemoryRepository<'manager> {
+    /// The identifier of the repository.
+    id: RepositoryId,
+
+    /// The manager from which this repository was opened.
+    manager: &'manager MemoryRepositoryManager,
+
+    /// The data of the repository, only visible to others once committed.
+    data: RepositoryData,
+
+    /// The lock held if the repository was opened for writing.
+    writer: Option<MutexGuard<'manager, ()>>,
+}
+
+impl MemoryRepository<'_> {
+    /// Returns an error if the repository was not opened for writing.
+    fn check_writable(&self, action: &str) -> io::Result<()> {
+        if self.writer.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::ReadOnlyFilesystem,
+                format!("cannot {action} in read-only transaction"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Updates the existence of a line in the repository.
+    fn update_line(&mut self, file_id: FileId, line_id: LineId) -> io::Result<()> {
+        let existence = (self.line_existence(file_id, line_id)?).unwrap_or(true);
+        let lines = self.data.lines.entry(file_id).or_default();
+        if existence {
+            lines.insert(line_id);
+        } else {
+            lines.remove(&line_id);
+        }
+        Ok(())
+    }
+}
+
+impl<'manager> Repository<'manager> for MemoryRepository<'manager> {
+    type Error = io::Error;
+
+    fn changes(&self) -> impl Iterator<Item = Result<(ChangeHash, Change), Self::Error>> {
+        self.data
+            .changes
+            .iter()
+            .map(|(&change_hash, &change)| Ok((change_hash, change)))
+    }
+
+    fn change(&self, change_hash: ChangeHash) -> Result<Option<Change>, Self::Error> {
+        Ok(self.data.changes.get(&change_hash).copied())
+    }
+
+    fn heads(&self, single_id: SingleId) -> Result<HashSet<ChangeHash>, Self::Error> {
+        Ok(self.data.heads.get(&single_id).cloned().unwrap_or_default())
+    }
+
+    fn existing_lines(&self, file_id: FileId) -> Result<HashSet<LineId>, Self::Error> {
+        Ok(self.data.lines.get(&file_id).cloned().unwrap_or_default())
+    }
+
+    fn files(&self) -> Result<HashSet<FileId>, Self::Error> {
+        Ok(self.data.files.keys().copied().collect())
+    }
+
+    fn apply(&mut self, change: Change) -> Result<ChangeHash, Self::Error> {
+        self.check_writable("apply changes")?;
+
+        // Check that the change is valid
+        change.validate().map_err(invalid_change)?;
+        let change_hash = change.calculate_hash();
+        for replaced_hash in change.replace {
+            if let Some(replaced) = self.data.changes.get(&replaced_hash) {
+                if replaced.single_id() != change.single_id() {
+                    return Err(invalid_change(InvalidChange::ForeignReplacement(
+                        replaced_hash,
+                    )));
+                }
+            }
+        }
+        if let Some(replacing_hashes) = self.data.reverse_heads.get(&change_hash) {
+            for replacing_hash in replacing_hashes {
+                if let Some(replacing) = self.data.changes.get(replacing_hash) {
+                    if replacing.single_id() != change.single_id() {
+                        return Err(invalid_change(InvalidChange::ForeignReplacement(
+                            *replacing_hash,
+                        )));
+                    }
+                }
+            }
+        }
+
+        // Insert the change
+        if self.data.changes.insert(change_hash, change).is_none() {
+            let count = self.data.files.entry(change.file_id()).or_default();
+            *count = count.saturating_add(1);
+        }
+
+        // Update the reversed heads
+        for replaced_hash in change.replace {
+            self.data
+                .reverse_heads
+                .entry(replaced_hash)
+                .or_default()
+                .insert(change_hash);
+        }
+
+        // Update the heads
+        let heads = self.data.heads.entry(change.single_id()).or_default();
+        for replaced_hash in change.replace {
+            heads.remove(&replaced_hash);
+        }
+        if !self.data.reverse_heads.contains_key(&change_hash) {
+            heads.insert(change_hash);
+        }
+
+        // Update the line existence if needed
+        if let ChangeContent::LineExistence {
+            file_id, line_id, ..
+        } = change.content
+        {
+            self.update_line(file_id, line_id)?;
+        }
+
+        // Return the change hash
+        Ok(change_hash)
+    }
+
+    fn unapply(&mut self, change_hash: ChangeHash) -> Result<(), Self::Error> {
+        self.check_writable("unapply changes")?;
+
+        // Remove the change
+        let Some(change) = self.data.changes.remove(&change_hash) else {
+            return Ok(());
+        };
+        if let Some(count) = self.data.files.get_mut(&change.file_id()) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.data.files.remove(&change.file_id());
+            }
+        }
+
+        // Update the heads
+        let heads = self.data.heads.entry(change.single_id()).or_default();
+        heads.remove(&change_hash);
+        for replaced_hash in change.replace {
+            let Some(reverse_heads) = self.data.reverse_heads.get_mut(&replaced_hash) else {
+                continue;
+            };
+
+            // Add the replaced change to the heads if it is applied and
+            // replaced ONLY by this change
+            if reverse_heads.len() == 1
+                && reverse_heads.contains(&change_hash)
+                && self.data.changes.contains_key(&replaced_hash)
+            {
+                heads.insert(replaced_hash);
+            }
+
+            // Update the replaced change by removing this change
+            reverse_heads.remove(&change_hash);
+            if reverse_heads.is_empty() {
+                self.data.reverse_heads.remove(&replaced_hash);
+            }
+        }
+
+        // Update the line existence if needed
+        if let ChangeContent::LineExistence {
+            file_id, line_id, ..
+        } = change.content
+        {
+            self.update_line(file_id, line_id)?;
+        }
+
+        // Return success
+        Ok(())
+    }
+
+    fn commit(self) -> Result<(), Self::Error> {
+        self.check_writable("commit")?;
+        let Ok(mut repositories) = self.manager.repositories.write() else {
+            return Err(io::Error::other("failed to write repositories".to_owned()));
+        };
+        repositories.insert(self.id, self.data);
+        Ok(())
+    }
+}
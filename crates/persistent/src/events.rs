@@ -0,0 +1,55 @@
+//! Defines the events recorded in the journal of a
+//! [`PersistentRepositoryManager`](crate::repository::PersistentRepositoryManager).
+//!
+//! Each event is given a sequence number when its transaction is committed,
+//! so external tools can poll for the events that happened since the last
+//! sequence number they have seen.
+
+use std::fmt::{self, Display};
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solipr_core::change::ChangeHash;
+use solipr_core::repository::RepositoryId;
+
+/// An event that happened in a repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, BorshDeserialize, BorshSerialize)]
+pub enum RepositoryEvent {
+    /// A change has been applied to a repository.
+    ChangeApplied(RepositoryId, ChangeHash),
+
+    /// A change has been unapplied from a repository.
+    ChangeUnapplied(RepositoryId, ChangeHash),
+
+    /// A repository has been created.
+    ///
+    /// Repositories without any applied change are considered not to exist,
+    /// so this is recorded before the first change applied to a repository
+    /// without any applied change.
+    RepositoryCreated(RepositoryId),
+}
+
+impl RepositoryEvent {
+    /// Returns the identifier of the repository in which this event happened.
+    #[must_use]
+    pub const fn repository_id(&self) -> RepositoryId {
+        match *self {
+            Self::ChangeApplied(repository_id, _)
+            | Self::ChangeUnapplied(repository_id, _)
+            | Self::RepositoryCreated(repository_id) => repository_id,
+        }
+    }
+}
+
+impl Display for RepositoryEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::ChangeApplied(repository_id, change_hash) => {
+                write!(f, "applied {repository_id} {change_hash}")
+            }
+            Self::ChangeUnapplied(repository_id, change_hash) => {
+                write!(f, "unapplied {repository_id} {change_hash}")
+            }
+            Self::RepositoryCreated(repository_id) => write!(f, "created {repository_id}"),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Defines the issues found when checking the integrity of a repository of a
+//! [`PersistentRepositoryManager`](crate::repository::PersistentRepositoryManager).
+
+use std::fmt::{self, Display};
+
+use solipr_core::change::{ChangeHash, FileId, LineId, SingleId};
+use solipr_core::registry::ContentHash;
+
+/// An inconsistency found in a repository.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IntegrityIssue {
+    /// A change is stored under a hash that is not its own.
+    ///
+    /// This issue cannot be repaired.
+    WrongHash(ChangeHash),
+
+    /// The changes replacing a change do not match the applied changes.
+    ReverseHeads(ChangeHash),
+
+    /// The heads of a single do not match the applied changes.
+    Heads(SingleId),
+
+    /// The existence of a line does not match its heads.
+    Line(FileId, LineId),
+
+    /// The number of changes modifying a file does not match the applied
+    /// changes.
+    File(FileId),
+
+    /// An entry of the given partition could not be decoded.
+    ///
+    /// When repairing, the entry is removed, and rebuilt if it belongs to an
+    /// index. An undecodable change is lost.
+    Undecodable(&'static str, Vec<u8>),
+
+    /// A content used by an applied change is not in the registry.
+    ///
+    /// This issue cannot be repaired.
+    MissingContent(ContentHash),
+}
+
+impl IntegrityIssue {
+    /// Returns `true` if this issue is fixed when repairing the repository.
+    #[must_use]
+    pub const fn is_repairable(&self) -> bool {
+        matches!(
+            *self,
+            Self::ReverseHeads(_)
+                | Self::Heads(_)
+                | Self::Line(_, _)
+                | Self::File(_)
+                | Self::Undecodable(_, _)
+        )
+    }
+}
+
+impl Display for IntegrityIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Undecodable(partition, ref key) => {
+                write!(
+                    f,
+                    "undecodable entry {key:02x?} in the {partition} partition"
+                )
+            }
+            Self::WrongHash(change_hash) => {
+                write!(f, "{change_hash} is stored under the wrong hash")
+            }
+            Self::ReverseHeads(change_hash) => {
+                write!(f, "invalid replacing changes for {change_hash}")
+            }
+            Self::Heads(single_id) => write!(f, "invalid heads for {single_id:?}"),
+            Self::Line(file_id, line_id) => {
+                write!(f, "invalid existence for {line_id} in {file_id}")
+            }
+            Self::File(file_id) => write!(f, "invalid change count for {file_id}"),
+            Self::MissingContent(content_hash) => write!(f, "{content_hash} is missing"),
+        }
+    }
+}
@@ -1,5 +1,9 @@
 //! An implementation of persistent data structures for Solipr that stores data
 //! on disk.
 
+pub mod events;
+pub mod integrity;
+pub mod migration;
 pub mod registry;
 pub mod repository;
+mod table;
@@ -0,0 +1,210 @@
+//! Defines the versioning of the on-disk format and the migrations between
+//! its versions.
+//!
+//! The version of the format is stored in the `meta` partition of the
+//! database. When a database is opened, the [`MIGRATIONS`] between its
+//! version and [`CURRENT_VERSION`] are run in order, and the version is
+//! updated after each one of them.
+
+use std::collections::HashMap;
+use std::io;
+
+use fjall::{Error, TransactionalKeyspace};
+use solipr_core::change::{Change, ChangeHash, FileId};
+use solipr_core::repository::RepositoryId;
+
+use crate::table::Table;
+
+/// A function migrating a database from a version of the format to the next.
+///
+/// A migration commits its own transactions, in batches of at most
+/// [`MIGRATION_BATCH_SIZE`] entries so that the database is never loaded in
+/// memory. The new version is only recorded once the migration returns, so
+/// an interrupted migration is run again from the start and must therefore
+/// give the same result when run several times.
+pub type Migration = fn(&TransactionalKeyspace) -> Result<(), Error>;
+
+/// The maximum number of entries written in a single transaction by a
+/// [`Migration`].
+pub const MIGRATION_BATCH_SIZE: usize = 4096;
+
+/// The migrations of the format, the migration at index `i` migrates a
+/// database from version `i + 1` to version `i + 2`.
+pub const MIGRATIONS: &[Migration] = &[index_files];
+
+/// The version of the format written by this version of Solipr.
+///
+/// Databases created before the format was versioned are at version `1`.
+#[expect(
+    clippy::as_conversions,
+    clippy::cast_possible_truncation,
+    reason = "there will never be more than u32::MAX migrations"
+)]
+pub const CURRENT_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+/// The name of the partition storing the metadata of the database.
+const META_PARTITION: &str = "meta";
+
+/// The key of the version of the format in the metadata partition.
+const VERSION_KEY: &str = "schema_version";
+
+/// Returns the version of the format of the given database.
+///
+/// If the database does not store its version, `None` will be returned.
+///
+/// # Errors
+///
+/// An error will be returned if the version could not be read.
+pub fn schema_version(keyspace: &TransactionalKeyspace) -> Result<Option<u32>, Error> {
+    if !keyspace.partition_exists(META_PARTITION) {
+        return Ok(None);
+    }
+    let meta: Table<String, u32> = Table::open(keyspace, META_PARTITION)?;
+    meta.get(&keyspace.read_tx(), &VERSION_KEY.to_owned())
+}
+
+/// Runs the [`MIGRATIONS`] needed to bring the given database to the
+/// [`CURRENT_VERSION`] and returns the version it was at.
+///
+/// # Errors
+///
+/// An error will be returned if the database was written by a newer version
+/// of Solipr or if a migration failed. In the latter case the database stays
+/// at the version of the last successful migration, along with the batches
+/// already committed by the failed one.
+pub fn migrate(keyspace: &TransactionalKeyspace) -> Result<u32, Error> {
+    migrate_with(keyspace, MIGRATIONS)
+}
+
+/// Runs the given migrations needed to bring the given database to the
+/// version following the last migration and returns the version it was at.
+///
+/// This is used by [`migrate`] and allows migrations to be tested on their
+/// own.
+///
+/// # Errors
+///
+/// An error will be returned if the database is newer than the last
+/// migration or if a migration failed. In the latter case the database stays
+/// at the version of the last successful migration.
+pub fn migrate_with(
+    keyspace: &TransactionalKeyspace,
+    migrations: &[Migration],
+) -> Result<u32, Error> {
+    // An empty database is created at the latest version, and a database
+    // without a version predates the versioning of the format
+    let latest = u32::try_from(migrations.len())
+        .ok()
+        .and_then(|count| count.checked_add(1))
+        .ok_or_else(|| Error::Io(io::Error::other("too many migrations")))?;
+    let stored = schema_version(keyspace)?;
+    let version = match stored {
+        Some(version) => version,
+        None if keyspace.partition_count() == 0 => latest,
+        None => 1,
+    };
+    if version > latest {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the database format version {version} is newer than {latest}"),
+        )));
+    }
+
+    // Run the missing migrations, recording each new version once its
+    // migration is done
+    let meta: Table<String, u32> = Table::open(keyspace, META_PARTITION)?;
+    if stored.is_none() {
+        let mut tx = keyspace.write_tx();
+        meta.insert(&mut tx, &VERSION_KEY.to_owned(), &version)?;
+        tx.commit()?;
+    }
+    for (from, migration) in (1_u32..).zip(migrations) {
+        if from < version {
+            continue;
+        }
+        migration(keyspace)?;
+        let mut tx = keyspace.write_tx();
+        meta.insert(&mut tx, &VERSION_KEY.to_owned(), &from.saturating_add(1))?;
+        tx.commit()?;
+    }
+    Ok(version)
+}
+
+/// Migrates a database from version `1` to version `2` by counting the
+/// applied changes modifying each file into the `files` partition.
+///
+/// The changes are read from a snapshot, one repository at a time, and the
+/// counts overwrite the existing ones, so the migration can be run again.
+fn index_files(keyspace: &TransactionalKeyspace) -> Result<(), Error> {
+    let changes: Table<(RepositoryId, ChangeHash), Change> = Table::open(keyspace, "changes")?;
+    let files: Table<(RepositoryId, FileId), u64> = Table::open(keyspace, "files")?;
+    let snapshot = keyspace.read_tx();
+    let mut batch = FileCountBatch::new(keyspace, &files);
+    let mut repository = None;
+    let mut counts = HashMap::<FileId, u64>::new();
+    for result in changes.prefix(&snapshot, &())? {
+        let ((repository_id, _), change) = result?;
+        if let Some(previous_id) = repository.filter(|&previous_id| previous_id != repository_id) {
+            batch.insert_all(previous_id, counts.drain())?;
+        }
+        repository = Some(repository_id);
+        let count = counts.entry(change.file_id()).or_default();
+        *count = count.saturating_add(1);
+    }
+    if let Some(repository_id) = repository {
+        batch.insert_all(repository_id, counts.drain())?;
+    }
+    batch.commit()
+}
+
+/// The file counts written by [`index_files`], committed every
+/// [`MIGRATION_BATCH_SIZE`] counts.
+struct FileCountBatch<'keyspace> {
+    /// The database in which the counts are written.
+    keyspace: &'keyspace TransactionalKeyspace,
+
+    /// The table in which the counts are written.
+    files: &'keyspace Table<(RepositoryId, FileId), u64>,
+
+    /// The counts that are not committed yet.
+    counts: Vec<((RepositoryId, FileId), u64)>,
+}
+
+impl<'keyspace> FileCountBatch<'keyspace> {
+    /// Creates an empty batch writing into the given table.
+    const fn new(
+        keyspace: &'keyspace TransactionalKeyspace,
+        files: &'keyspace Table<(RepositoryId, FileId), u64>,
+    ) -> Self {
+        Self {
+            keyspace,
+            files,
+            counts: Vec::new(),
+        }
+    }
+
+    /// Adds the counts of the files of a repository to the batch, committing
+    /// it whenever it is full.
+    fn insert_all(
+        &mut self,
+        repository_id: RepositoryId,
+        counts: impl Iterator<Item = (FileId, u64)>,
+    ) -> Result<(), Error> {
+        for (file_id, count) in counts {
+            self.counts.push(((repository_id, file_id), count));
+            if self.counts.len() >= MIGRATION_BATCH_SIZE {
+                self.commit()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the counts of the batch in a single transaction.
+    fn commit(&mut self) -> Result<(), Error> {
+        let mut tx = self.keyspace.write_tx();
+        for (key, count) in self.counts.drain(..) {
+            self.files.insert(&mut tx, &key, &count)?;
+        }
+        tx.commit()
+    }
+}
@@ -1,17 +1,50 @@
-//! Defines a persistent implementation of [Registry].
+//! Defines persistent implementations of [Registry] and [`AsyncRegistry`].
 
 use std::fs::{self, File};
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use base64::prelude::*;
 use sha2::{Digest, Sha256};
-use solipr_core::registry::{ContentHash, Registry};
+use solipr_core::registry::{AsyncRegistry, ContentHash, Registry};
+use tokio::fs as async_fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Semaphore;
+
+/// Returns the path of the file in which the content with the given hash is
+/// stored.
+fn content_path(folder: &Path, hash: ContentHash) -> PathBuf {
+    let encoded_hash = BASE64_URL_SAFE_NO_PAD.encode(hash.as_bytes());
+    let (subfolder, file) = encoded_hash.split_at(2);
+    folder.join(subfolder).join(file)
+}
+
+/// Returns the error used when a content does not have the expected hash.
+fn hash_mismatch(hash: ContentHash, expected: ContentHash) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("the content has the hash {hash} instead of {expected}"),
+    )
+}
+
+/// Returns the error used when a content is bigger than the maximum size of
+/// a registry.
+fn content_too_large(max_content_size: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::FileTooLarge,
+        format!("the content is bigger than the maximum size of {max_content_size} bytes"),
+    )
+}
 
 /// A persistent implementation of [Registry].
 pub struct PersistentRegistry {
     /// The path to the folder where the contents are stored.
     folder: PathBuf,
+
+    /// The maximum size of a content in bytes, if any.
+    max_content_size: Option<u64>,
 }
 
 impl PersistentRegistry {
@@ -20,44 +53,123 @@ impl PersistentRegistry {
     pub fn new(folder: impl Into<PathBuf>) -> Self {
         Self {
             folder: folder.into(),
+            max_content_size: None,
         }
     }
-}
 
-impl Registry for PersistentRegistry {
-    type Error = io::Error;
+    /// Limits the size of the contents that can be written in this registry.
+    ///
+    /// Writing a content bigger than `max_content_size` bytes will fail with
+    /// an [`io::ErrorKind::FileTooLarge`] error.
+    #[must_use]
+    pub const fn with_max_content_size(mut self, max_content_size: u64) -> Self {
+        self.max_content_size = Some(max_content_size);
+        self
+    }
 
-    fn read(&self, hash: ContentHash) -> Result<Option<impl Read>, Self::Error> {
-        let encoded_hash = BASE64_URL_SAFE_NO_PAD.encode(hash.as_bytes());
-        #[expect(
-            clippy::string_slice,
-            reason = "there is always more than 2 characters in a ContentHash"
-        )]
-        match File::open(
-            self.folder
-                .join(&encoded_hash[..2])
-                .join(&encoded_hash[2..]),
-        ) {
-            Ok(file) => Ok(Some(file)),
-            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
-            Err(err) => Err(err),
+    /// Copies all the contents of this registry into the given folder.
+    ///
+    /// The contents are hard linked when possible, which is safe because they
+    /// are never modified. Otherwise they are copied into a temporary file
+    /// that is then moved into place, so an interrupted backup never leaves a
+    /// truncated content. The folder can then be opened as another
+    /// [`PersistentRegistry`] to restore them.
+    ///
+    /// When the repositories using this registry are backed up too, they must
+    /// be backed up first, as explained in the `backup` function of
+    /// [`PersistentRepositoryManager`](crate::repository::PersistentRepositoryManager).
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if a content could not be copied.
+    pub fn backup(&self, folder: impl AsRef<Path>) -> io::Result<()> {
+        let folder = folder.as_ref();
+        for hash in self.contents()? {
+            let target = content_path(folder, hash);
+            if target.exists() {
+                continue;
+            }
+            if let Some(target_dir) = target.parent() {
+                fs::create_dir_all(target_dir)?;
+            }
+            let source = content_path(&self.folder, hash);
+            if fs::hard_link(&source, &target).is_err() {
+                let temp_file_path = folder.join(uuid::Uuid::now_v7().to_string());
+                if let Err(err) = fs::copy(&source, &temp_file_path)
+                    .and_then(|_| fs::rename(&temp_file_path, &target))
+                {
+                    #[expect(
+                        clippy::unused_result_ok,
+                        reason = "the copy error is more relevant than the cleanup error"
+                    )]
+                    fs::remove_file(&temp_file_path).ok();
+                    return Err(err);
+                }
+            }
         }
+        Ok(())
     }
 
-    fn write(&self, mut content: impl Read) -> Result<ContentHash, Self::Error> {
+    /// Writes the given content into the registry and returns its hash.
+    ///
+    /// If an `expected` hash is given and the content does not have it, the
+    /// content is removed before being moved into the registry.
+    fn write_content(
+        &self,
+        content: impl Read,
+        expected: Option<ContentHash>,
+    ) -> io::Result<ContentHash> {
         // Create the folder if it doesn't exist
         if !self.folder.exists() {
             fs::create_dir_all(&self.folder)?;
         }
 
-        // Create a temporary file to store the content in
+        // Write the content into a temporary file and check its hash
         let temp_file_path = self.folder.join(uuid::Uuid::now_v7().to_string());
-        let mut temp_file = File::create(&temp_file_path)?;
+        let hash = match self
+            .write_temp_file(&temp_file_path, content)
+            .and_then(|hash| match expected {
+                Some(expected) if hash != expected => Err(hash_mismatch(hash, expected)),
+                Some(_) | None => Ok(hash),
+            }) {
+            Ok(hash) => hash,
+            Err(err) => {
+                #[expect(
+                    clippy::unused_result_ok,
+                    reason = "the write error is more relevant than the cleanup error"
+                )]
+                fs::remove_file(&temp_file_path).ok();
+                return Err(err);
+            }
+        };
+
+        // Move the temporary file into the correct location
+        let path = content_path(&self.folder, hash);
+        if let Some(path_dir) = path.parent() {
+            if !path_dir.exists() {
+                fs::create_dir_all(path_dir)?;
+            }
+        }
+        fs::rename(temp_file_path, path)?;
+
+        // Return the hash of the content
+        Ok(hash)
+    }
+
+    /// Writes the given content into the given temporary file and returns its
+    /// hash.
+    fn write_temp_file(
+        &self,
+        temp_file_path: &Path,
+        mut content: impl Read,
+    ) -> io::Result<ContentHash> {
+        let mut temp_file = File::create(temp_file_path)?;
 
         // Loop 32 bytes at a time and update the hasher
         // until we reach the end of the content
         let mut hasher = Sha256::new();
         let mut buffer = [0; 32];
+        let mut content_size: u64 = 0;
         loop {
             let byte_count = match content.read(&mut buffer) {
                 Ok(0) => break,
@@ -65,6 +177,12 @@ impl Registry for PersistentRegistry {
                 Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
                 Err(err) => return Err(err),
             };
+            content_size = content_size.saturating_add(byte_count as u64);
+            if let Some(max_content_size) = self.max_content_size {
+                if content_size > max_content_size {
+                    return Err(content_too_large(max_content_size));
+                }
+            }
             #[expect(
                 clippy::indexing_slicing,
                 reason = "byte_count is always smaller or equal than 32"
@@ -77,21 +195,257 @@ impl Registry for PersistentRegistry {
             temp_file.write_all(&buffer[..byte_count])?;
         }
         temp_file.flush()?;
-        drop(temp_file);
 
         // Create a unique hash for the content
-        let hash = ContentHash::new(hasher.finalize().into());
-        let encoded_hash = BASE64_URL_SAFE_NO_PAD.encode(hash.as_bytes());
+        Ok(ContentHash::new(hasher.finalize().into()))
+    }
+}
+
+impl Registry for PersistentRegistry {
+    type Error = io::Error;
+
+    fn read(&self, hash: ContentHash) -> Result<Option<impl Read>, Self::Error> {
+        match File::open(content_path(&self.folder, hash)) {
+            Ok(file) => Ok(Some(file)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn write(&self, content: impl Read) -> Result<ContentHash, Self::Error> {
+        self.write_content(content, None)
+    }
+
+    fn write_verified(&self, content: impl Read, expected: ContentHash) -> Result<(), Self::Error> {
+        self.write_content(content, Some(expected)).map(|_| ())
+    }
+
+    fn size(&self, hash: ContentHash) -> Result<Option<u64>, Self::Error> {
+        match fs::metadata(content_path(&self.folder, hash)) {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn written_at(&self, hash: ContentHash) -> Result<Option<SystemTime>, Self::Error> {
+        match fs::metadata(content_path(&self.folder, hash)) {
+            Ok(metadata) => Ok(Some(metadata.modified()?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn contents(&self) -> Result<Vec<ContentHash>, Self::Error> {
+        let entries = match fs::read_dir(&self.folder) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        // Every content is stored in a subfolder named after the start of its
+        // hash, the other files are temporary files
+        let mut contents = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let subfolder = entry.file_name();
+            for file in fs::read_dir(entry.path())? {
+                let file = file?;
+                let mut encoded_hash = subfolder.clone();
+                encoded_hash.push(file.file_name());
+                if let Some(Ok(hash)) = encoded_hash.to_str().map(str::parse) {
+                    if content_path(&self.folder, hash) == file.path() {
+                        contents.push(hash);
+                    }
+                }
+            }
+        }
+        Ok(contents)
+    }
+
+    fn remove(&self, hash: ContentHash) -> Result<bool, Self::Error> {
+        match fs::remove_file(content_path(&self.folder, hash)) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn remove_if_written_before(
+        &self,
+        hash: ContentHash,
+        time: SystemTime,
+    ) -> Result<bool, Self::Error> {
+        // Move the content out of the way first, so a concurrent write puts a
+        // new file in place instead of having its file removed
+        let path = content_path(&self.folder, hash);
+        let removed_path = self.folder.join(uuid::Uuid::now_v7().to_string());
+        match fs::rename(&path, &removed_path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        }
+
+        // Put the content back if it was written again before being moved
+        match fs::metadata(&removed_path).and_then(|metadata| metadata.modified()) {
+            Ok(written_at) if written_at <= time => {
+                fs::remove_file(&removed_path)?;
+                Ok(true)
+            }
+            Ok(_) => fs::rename(&removed_path, &path).map(|()| false),
+            Err(err) => {
+                #[expect(
+                    clippy::unused_result_ok,
+                    reason = "the metadata error is more relevant than the restore error"
+                )]
+                fs::rename(&removed_path, &path).ok();
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A persistent implementation of [`AsyncRegistry`].
+///
+/// It stores its contents the same way as [`PersistentRegistry`], so both can
+/// be opened on the same folder.
+pub struct AsyncPersistentRegistry {
+    /// The path to the folder where the contents are stored.
+    folder: PathBuf,
+
+    /// The slots limiting the number of writes done at the same time.
+    write_slots: Semaphore,
+
+    /// The maximum size of a content in bytes, if any.
+    max_content_size: Option<u64>,
+}
+
+impl AsyncPersistentRegistry {
+    /// Creates a new [`AsyncPersistentRegistry`] from the specified folder.
+    ///
+    /// At most `write_slots` contents will be written at the same time, the
+    /// other writes will wait for a slot to be released.
+    #[must_use]
+    pub fn new(folder: impl Into<PathBuf>, write_slots: NonZeroUsize) -> Self {
+        Self {
+            folder: folder.into(),
+            write_slots: Semaphore::new(write_slots.get()),
+            max_content_size: None,
+        }
+    }
+
+    /// Limits the size of the contents that can be written in this registry.
+    ///
+    /// Writing a content bigger than `max_content_size` bytes will fail with
+    /// an [`io::ErrorKind::FileTooLarge`] error.
+    #[must_use]
+    pub const fn with_max_content_size(mut self, max_content_size: u64) -> Self {
+        self.max_content_size = Some(max_content_size);
+        self
+    }
+
+    /// Writes the given content into the given temporary file and returns its
+    /// hash.
+    async fn write_temp_file(
+        &self,
+        temp_file_path: &Path,
+        mut content: impl AsyncRead + Unpin + Send,
+    ) -> io::Result<ContentHash> {
+        let mut temp_file = async_fs::File::create(temp_file_path).await?;
+
+        // Loop 4096 bytes at a time and update the hasher
+        // until we reach the end of the content
+        let mut hasher = Sha256::new();
+        let mut buffer = [0; 4096];
+        let mut content_size: u64 = 0;
+        loop {
+            let byte_count = match content.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(byte_count) => byte_count,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            content_size = content_size.saturating_add(byte_count as u64);
+            if let Some(max_content_size) = self.max_content_size {
+                if content_size > max_content_size {
+                    return Err(content_too_large(max_content_size));
+                }
+            }
+            #[expect(
+                clippy::indexing_slicing,
+                reason = "byte_count is always smaller or equal than 4096"
+            )]
+            hasher.update(&buffer[..byte_count]);
+            #[expect(
+                clippy::indexing_slicing,
+                reason = "byte_count is always smaller or equal than 4096"
+            )]
+            temp_file.write_all(&buffer[..byte_count]).await?;
+        }
+        temp_file.flush().await?;
+
+        // Create a unique hash for the content
+        Ok(ContentHash::new(hasher.finalize().into()))
+    }
+}
+
+impl AsyncRegistry for AsyncPersistentRegistry {
+    type Error = io::Error;
+
+    async fn read(
+        &self,
+        hash: ContentHash,
+    ) -> Result<Option<impl AsyncRead + Unpin + Send>, Self::Error> {
+        match async_fs::File::open(content_path(&self.folder, hash)).await {
+            Ok(file) => Ok(Some(file)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn write(
+        &self,
+        content: impl AsyncRead + Unpin + Send,
+    ) -> Result<ContentHash, Self::Error> {
+        // Wait for a write slot to be available
+        let _slot = self.write_slots.acquire().await.map_err(io::Error::other)?;
+
+        // Create the folder if it doesn't exist
+        async_fs::create_dir_all(&self.folder).await?;
+
+        // Write the content into a temporary file
+        let temp_file_path = self.folder.join(uuid::Uuid::now_v7().to_string());
+        let hash = match self.write_temp_file(&temp_file_path, content).await {
+            Ok(hash) => hash,
+            Err(err) => {
+                #[expect(
+                    clippy::unused_result_ok,
+                    reason = "the write error is more relevant than the cleanup error"
+                )]
+                async_fs::remove_file(&temp_file_path).await.ok();
+                return Err(err);
+            }
+        };
 
         // Move the temporary file into the correct location
-        let (subfolder, file) = encoded_hash.split_at(2);
-        let path_dir = self.folder.join(subfolder);
-        if !path_dir.exists() {
-            fs::create_dir_all(&path_dir)?;
+        let path = content_path(&self.folder, hash);
+        if let Some(path_dir) = path.parent() {
+            async_fs::create_dir_all(path_dir).await?;
         }
-        fs::rename(temp_file_path, path_dir.join(file))?;
+        async_fs::rename(temp_file_path, path).await?;
 
         // Return the hash of the content
         Ok(hash)
     }
+
+    async fn size(&self, hash: ContentHash) -> Result<Option<u64>, Self::Error> {
+        match async_fs::metadata(content_path(&self.folder, hash)).await {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
 }
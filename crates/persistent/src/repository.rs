@@ -1,18 +1,33 @@
 //! An implementation of the [`RepositoryManager`] and [Repository] traits that
 //! stores data in persistent storage (on disk).
 
-use std::collections::HashSet;
-use std::io;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::hash::Hash;
 use std::path::Path;
+use std::{fs, io, iter};
 
+use borsh::{BorshDeserialize, BorshSerialize};
 use fjall::{
-    Config, Error, PartitionCreateOptions, ReadTransaction, Slice, TransactionalKeyspace,
-    TransactionalPartitionHandle, WriteTransaction,
+    Config, Error, PartitionCreateOptions, PersistMode, ReadTransaction, Slice,
+    TransactionalKeyspace, TransactionalPartitionHandle, WriteTransaction,
 };
-use solipr_core::change::{Change, ChangeContent, ChangeHash, FileId, LineId, SingleId};
+use solipr_core::change::{
+    Change, ChangeContent, ChangeHash, FileId, InvalidChange, LineId, SingleId,
+};
+use solipr_core::registry::{ContentHash, Registry};
 use solipr_core::repository::head::HeadExt;
 use solipr_core::repository::{Repository, RepositoryId, RepositoryManager};
 
+use crate::events::RepositoryEvent;
+use crate::integrity::IntegrityIssue;
+use crate::migration;
+use crate::table::{ReadableTransaction, Table};
+
+/// The maximum number of entries written in a single transaction by
+/// [`PersistentRepositoryManager::backup`].
+pub const BACKUP_BATCH_SIZE: usize = 4096;
+
 /// An implementation of the [`RepositoryManager`] that stores data in
 /// persistent storage (on disk).
 pub struct PersistentRepositoryManager {
@@ -21,25 +36,42 @@ pub struct PersistentRepositoryManager {
     /// It is an handle to the database.
     keyspace: TransactionalKeyspace,
 
-    /// A handle to the change partition of the database.
+    /// The change table of the database.
+    ///
+    /// This table stores all the changes made to the repository.
+    changes: Table<(RepositoryId, ChangeHash), Change>,
+
+    /// The reverse heads table of the database.
+    ///
+    /// This table stores all the parent changes of all changes.
+    reverse_heads: Table<(RepositoryId, ChangeHash), HashSet<ChangeHash>>,
+
+    /// The head table of the database.
+    ///
+    /// This table stores an index to find rapidly the heads of a single.
+    heads: Table<(RepositoryId, SingleId), HashSet<ChangeHash>>,
+
+    /// The existing lines table of the database.
     ///
-    /// This partition stores all the changes made to the repository.
-    changes: TransactionalPartitionHandle,
+    /// This table stores all the existing lines of the repository.
+    lines: Table<(RepositoryId, FileId, LineId), ()>,
 
-    /// A handle to the reverse heads partition of the database.
+    /// The files table of the database.
     ///
-    /// This partition stores all the parent changes of all changes.
-    reverse_heads: TransactionalPartitionHandle,
+    /// This table stores the number of applied changes modifying each file
+    /// of the repository.
+    files: Table<(RepositoryId, FileId), u64>,
 
-    /// A handle to the head partition of the database.
+    /// A handle to the events partition of the database.
     ///
-    /// This partition stores an index to find rapidly the heads of a single.
-    heads: TransactionalPartitionHandle,
+    /// This partition stores the journal of all the events that happened in
+    /// the repositories, indexed by their sequence number.
+    events: TransactionalPartitionHandle,
 
-    /// A handle to the existing lines partition of the database.
+    /// The forks table of the database.
     ///
-    /// This partition stores all the existing lines of the repository.
-    lines: TransactionalPartitionHandle,
+    /// This table stores the repository from which each fork was created.
+    forks: Table<RepositoryId, RepositoryId>,
 }
 
 impl PersistentRepositoryManager {
@@ -47,22 +79,528 @@ impl PersistentRepositoryManager {
     ///
     /// # Errors
     ///
-    /// An error will be returned if the folder could not be opened.
+    /// An error will be returned if the folder could not be opened, if it was
+    /// written by a newer version of Solipr or if it could not be migrated.
     pub fn create(folder: impl AsRef<Path>) -> Result<Self, Error> {
         let keyspace = Config::new(folder).open_transactional()?;
-        let changes = keyspace.open_partition("changes", PartitionCreateOptions::default())?;
-        let reverse_heads =
-            keyspace.open_partition("reverse_heads", PartitionCreateOptions::default())?;
-        let heads = keyspace.open_partition("heads", PartitionCreateOptions::default())?;
-        let lines = keyspace.open_partition("lines", PartitionCreateOptions::default())?;
+        migration::migrate(&keyspace)?;
+        let changes = Table::open(&keyspace, "changes")?;
+        let reverse_heads = Table::open(&keyspace, "reverse_heads")?;
+        let heads = Table::open(&keyspace, "heads")?;
+        let lines = Table::open(&keyspace, "lines")?;
+        let files = Table::open(&keyspace, "files")?;
+        let events = keyspace.open_partition("events", PartitionCreateOptions::default())?;
+        let forks = Table::open(&keyspace, "forks")?;
         Ok(Self {
             keyspace,
             changes,
             reverse_heads,
             heads,
             lines,
+            files,
+            events,
+            forks,
         })
     }
+
+    /// Creates a new repository containing all the changes of the given
+    /// repository and returns its identifier.
+    ///
+    /// The new repository is independent from the source repository, changes
+    /// applied to one of them are not applied to the other.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the source repository does not contain
+    /// any change, if it could not be read or if the new repository could not
+    /// be written.
+    pub fn fork(&self, source_id: RepositoryId) -> Result<RepositoryId, Error> {
+        let changes = self
+            .open_read(source_id)?
+            .changes()
+            .collect::<Result<Vec<_>, _>>()?;
+        if changes.is_empty() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("cannot fork {source_id} as it does not contain any change"),
+            )));
+        }
+        let fork_id = RepositoryId::create_new();
+        let mut fork = self.open_write(fork_id)?;
+        for (_, change) in changes {
+            fork.apply(change)?;
+        }
+        let RepositoryTransaction::Write(ref mut tx) = fork.transaction else {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::ReadOnlyFilesystem,
+                "cannot fork into read-only transaction",
+            )));
+        };
+        self.forks.insert(tx, &fork_id, &source_id)?;
+        fork.commit().map(|()| fork_id)
+    }
+
+    /// Returns the repository from which the given repository was forked.
+    ///
+    /// If the repository was not created using [`Self::fork`], `None` will be
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the origin could not be read.
+    pub fn fork_origin(&self, repository_id: RepositoryId) -> Result<Option<RepositoryId>, Error> {
+        self.forks.get(&self.keyspace.read_tx(), &repository_id)
+    }
+
+    /// Writes a consistent copy of all the repositories into a new database
+    /// in the given folder.
+    ///
+    /// The copy only contains the transactions committed before this
+    /// function is called, and it can be restored by opening the folder with
+    /// [`Self::create`]. It is written in batches of [`BACKUP_BATCH_SIZE`]
+    /// entries so that the database is never loaded in memory, into a
+    /// temporary folder next to the given one that is only renamed to it once
+    /// complete. An interrupted backup therefore never leaves a partial
+    /// database in the folder.
+    ///
+    /// To back up a [`PersistentRegistry`](crate::registry::PersistentRegistry)
+    /// along with the repositories, this function must be called before
+    /// backing up the registry, and garbage must not be collected in between,
+    /// so that the backed up registry contains all the contents used by the
+    /// backed up repositories.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the folder is not empty or if the copy
+    /// could not be written.
+    pub fn backup(&self, folder: impl AsRef<Path>) -> Result<(), Error> {
+        let folder = folder.as_ref();
+        if folder.exists() && folder.read_dir()?.next().is_some() {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "cannot backup into a non-empty folder",
+            )));
+        }
+        let Some(folder_name) = folder.file_name() else {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot backup into a folder without a name",
+            )));
+        };
+
+        // Write the copy next to the folder and move it into place
+        let mut temp_folder_name = OsString::from(".");
+        temp_folder_name.push(folder_name);
+        temp_folder_name.push(format!(".{}", uuid::Uuid::now_v7()));
+        let temp_folder = folder.with_file_name(temp_folder_name);
+        let result = self
+            .write_backup(&temp_folder)
+            .and_then(|()| fs::rename(&temp_folder, folder).map_err(Error::Io));
+        if result.is_err() {
+            #[expect(
+                clippy::unused_result_ok,
+                reason = "the backup error is more relevant than the cleanup error"
+            )]
+            fs::remove_dir_all(&temp_folder).ok();
+        }
+        result
+    }
+
+    /// Writes a copy of all the repositories into a new database in the given
+    /// folder, committing every [`BACKUP_BATCH_SIZE`] entries.
+    ///
+    /// The database is closed when this function returns, so the folder can
+    /// be moved.
+    fn write_backup(&self, folder: &Path) -> Result<(), Error> {
+        // Copy every partition from the same snapshot
+        let snapshot = self.keyspace.read_tx();
+        let backup = Config::new(folder).open_transactional()?;
+        let mut tx = backup.write_tx();
+        let mut batch_size = 0_usize;
+        for name in self.keyspace.list_partitions() {
+            let source = self
+                .keyspace
+                .open_partition(&name, PartitionCreateOptions::default())?;
+            let target = backup.open_partition(&name, PartitionCreateOptions::default())?;
+            for entry in snapshot.iter(&source) {
+                let (key, value) = entry?;
+                tx.insert(&target, key, value);
+                batch_size = batch_size.saturating_add(1);
+                if batch_size >= BACKUP_BATCH_SIZE {
+                    tx.commit()?;
+                    tx = backup.write_tx();
+                    batch_size = 0;
+                }
+            }
+        }
+        tx.commit()
+            .and_then(|()| backup.persist(PersistMode::SyncAll))
+    }
+
+    /// Checks that the entries of a repository can be decoded, that its
+    /// indexes match its applied changes and that the contents they use are
+    /// in the registry, and returns the issues found sorted.
+    ///
+    /// If `repair` is `true`, the repairable issues are fixed by removing the
+    /// undecodable entries and rebuilding the indexes from the applied
+    /// changes. Otherwise only a snapshot of the repository is read, so the
+    /// check does not block the writers.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the repository could not be read or if it
+    /// could not be repaired.
+    pub fn check_integrity(
+        &self,
+        repository_id: RepositoryId,
+        registry: &impl Registry<Error = io::Error>,
+        repair: bool,
+    ) -> Result<Vec<IntegrityIssue>, Error> {
+        let mut tx = if repair {
+            RepositoryTransaction::Write(self.keyspace.write_tx())
+        } else {
+            RepositoryTransaction::Read(self.keyspace.read_tx())
+        };
+        let mut issues = Vec::new();
+
+        // Read the applied changes, skipping the undecodable ones
+        let mut changes_repairs = Repairs::default();
+        let changes = self.read_changes(&tx, repository_id, &mut issues, &mut changes_repairs)?;
+
+        // Compute the expected indexes from the applied changes
+        let mut reverse_heads = HashMap::<ChangeHash, HashSet<ChangeHash>>::new();
+        for (&change_hash, change) in &changes {
+            for replaced_hash in change.replace {
+                reverse_heads
+                    .entry(replaced_hash)
+                    .or_default()
+                    .insert(change_hash);
+            }
+        }
+        let mut heads = HashMap::<SingleId, HashSet<ChangeHash>>::new();
+        let mut files = HashMap::<FileId, u64>::new();
+        for (&change_hash, change) in &changes {
+            let count = files.entry(change.file_id()).or_default();
+            *count = count.saturating_add(1);
+            let single_heads = heads.entry(change.single_id()).or_default();
+            if !reverse_heads.contains_key(&change_hash) {
+                single_heads.insert(change_hash);
+            }
+        }
+        let mut lines = HashSet::new();
+        for (&single_id, single_heads) in &heads {
+            if let SingleId::LineExistence(file_id, line_id) = single_id {
+                if line_should_exist(single_heads, &changes) {
+                    lines.insert((file_id, line_id));
+                }
+            }
+        }
+
+        // Compare them with the stored indexes
+        let reverse_heads_repairs = check_index(
+            &self.reverse_heads,
+            &tx,
+            repository_id,
+            reverse_heads,
+            IntegrityIssue::ReverseHeads,
+            &mut issues,
+        )?;
+        let heads_repairs = check_index(
+            &self.heads,
+            &tx,
+            repository_id,
+            heads,
+            IntegrityIssue::Heads,
+            &mut issues,
+        )?;
+        let files_repairs = check_index(
+            &self.files,
+            &tx,
+            repository_id,
+            files,
+            IntegrityIssue::File,
+            &mut issues,
+        )?;
+        let lines_repairs = self.check_lines(&tx, repository_id, lines, &mut issues)?;
+
+        // Check that the used contents exist
+        for change in changes.values() {
+            if let ChangeContent::LineContent { content, .. } = change.content {
+                if !registry.exists(content).map_err(Error::Io)? {
+                    issues.push(IntegrityIssue::MissingContent(content));
+                }
+            }
+        }
+        issues.sort_unstable();
+        issues.dedup();
+
+        // Only repair the repository if asked to
+        if let RepositoryTransaction::Write(ref mut write_tx) = tx {
+            changes_repairs.apply(&self.changes, write_tx, repository_id)?;
+            reverse_heads_repairs.apply(&self.reverse_heads, write_tx, repository_id)?;
+            heads_repairs.apply(&self.heads, write_tx, repository_id)?;
+            files_repairs.apply(&self.files, write_tx, repository_id)?;
+            self.apply_lines_repairs(write_tx, repository_id, lines_repairs)?;
+        }
+        match tx {
+            RepositoryTransaction::Read(_) => Ok(issues),
+            RepositoryTransaction::Write(write_tx) => write_tx.commit().map(|()| issues),
+        }
+    }
+
+    /// Returns the changes of a repository that can be decoded, and reports
+    /// the other ones in `issues` and their repairs in `repairs`.
+    fn read_changes(
+        &self,
+        tx: &impl ReadableTransaction,
+        repository_id: RepositoryId,
+        issues: &mut Vec<IntegrityIssue>,
+        repairs: &mut Repairs<ChangeHash, Change>,
+    ) -> Result<HashMap<ChangeHash, Change>, Error> {
+        let mut changes = HashMap::new();
+        for result in self.changes.prefix_entries(tx, &repository_id)? {
+            let (raw_key, entry) = result?;
+            let Some(((_, change_hash), change)) = entry else {
+                issues.push(IntegrityIssue::Undecodable(
+                    self.changes.name(),
+                    raw_key.to_vec(),
+                ));
+                repairs.undecodable.push(raw_key);
+                continue;
+            };
+            if change.calculate_hash() != change_hash {
+                issues.push(IntegrityIssue::WrongHash(change_hash));
+            }
+            changes.insert(change_hash, change);
+        }
+        Ok(changes)
+    }
+
+    /// Compares the existing lines of a repository with the expected ones,
+    /// reports the differences in `issues` and returns their repairs.
+    fn check_lines(
+        &self,
+        tx: &impl ReadableTransaction,
+        repository_id: RepositoryId,
+        mut expected: HashSet<(FileId, LineId)>,
+        issues: &mut Vec<IntegrityIssue>,
+    ) -> Result<Repairs<(FileId, LineId), ()>, Error> {
+        let mut repairs = Repairs::default();
+        for result in self.lines.prefix_entries(tx, &repository_id)? {
+            let (raw_key, entry) = result?;
+            let Some(((_, file_id, line_id), ())) = entry else {
+                issues.push(IntegrityIssue::Undecodable(
+                    self.lines.name(),
+                    raw_key.to_vec(),
+                ));
+                repairs.undecodable.push(raw_key);
+                continue;
+            };
+            if !expected.remove(&(file_id, line_id)) {
+                issues.push(IntegrityIssue::Line(file_id, line_id));
+                repairs.fixes.push(((file_id, line_id), None));
+            }
+        }
+        for (file_id, line_id) in expected {
+            issues.push(IntegrityIssue::Line(file_id, line_id));
+            repairs.fixes.push(((file_id, line_id), Some(())));
+        }
+        Ok(repairs)
+    }
+
+    /// Applies the repairs returned by [`Self::check_lines`].
+    fn apply_lines_repairs(
+        &self,
+        tx: &mut WriteTransaction,
+        repository_id: RepositoryId,
+        repairs: Repairs<(FileId, LineId), ()>,
+    ) -> Result<(), Error> {
+        for raw_key in repairs.undecodable {
+            self.lines.remove_raw(tx, &raw_key);
+        }
+        for ((file_id, line_id), value) in repairs.fixes {
+            let key = (repository_id, file_id, line_id);
+            match value {
+                Some(()) => self.lines.insert(tx, &key, &())?,
+                None => self.lines.remove(tx, &key)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns at most `limit` events recorded after the given sequence number
+    /// along with their own sequence number.
+    ///
+    /// Sequence numbers start at 1, so passing 0 starts from the beginning of
+    /// the journal. To read the whole journal, call this again with the last
+    /// returned sequence number until fewer than `limit` events are returned.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the journal could not be read.
+    pub fn events(&self, since: u64, limit: usize) -> Result<Vec<(u64, RepositoryEvent)>, Error> {
+        let tx = self.keyspace.read_tx();
+        tx.range(&self.events, since.saturating_add(1).to_be_bytes()..)
+            .take(limit)
+            .map(|result| {
+                let (key, value) = result?;
+                Ok((
+                    decode_sequence(&key)?,
+                    borsh::from_slice::<RepositoryEvent>(&value)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Updates the number of applied changes modifying a file in the given
+    /// transaction.
+    fn count_file_change(
+        &self,
+        tx: &mut WriteTransaction,
+        repository_id: RepositoryId,
+        file_id: FileId,
+        applied: bool,
+    ) -> Result<(), Error> {
+        let key = (repository_id, file_id);
+        let count = self.files.get(tx, &key)?.unwrap_or_default();
+        let count = if applied {
+            count.saturating_add(1)
+        } else {
+            count.saturating_sub(1)
+        };
+        if count == 0 {
+            self.files.remove(tx, &key)
+        } else {
+            self.files.insert(tx, &key, &count)
+        }
+    }
+
+    /// Appends an event to the journal in the given transaction.
+    fn record_event(&self, tx: &mut WriteTransaction, event: RepositoryEvent) -> Result<(), Error> {
+        let sequence = match tx.last_key_value(&self.events)? {
+            Some((key, _)) => decode_sequence(&key)?.saturating_add(1),
+            None => 1,
+        };
+        tx.insert(&self.events, sequence.to_be_bytes(), borsh::to_vec(&event)?);
+        Ok(())
+    }
+}
+
+/// The repairs of the entries of a repository in a [Table], found by
+/// [`PersistentRepositoryManager::check_integrity`].
+struct Repairs<K, V> {
+    /// The raw keys of the entries that could not be decoded.
+    undecodable: Vec<Slice>,
+
+    /// The entries to rewrite with their expected value, or to remove if it
+    /// is `None`.
+    fixes: Vec<(K, Option<V>)>,
+}
+
+impl<K, V> Default for Repairs<K, V> {
+    fn default() -> Self {
+        Self {
+            undecodable: Vec::new(),
+            fixes: Vec::new(),
+        }
+    }
+}
+
+impl<K: BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize> Repairs<K, V> {
+    /// Applies the repairs to the entries of the given repository in the
+    /// given table.
+    fn apply(
+        self,
+        table: &Table<(RepositoryId, K), V>,
+        tx: &mut WriteTransaction,
+        repository_id: RepositoryId,
+    ) -> Result<(), Error> {
+        for key in self.undecodable {
+            table.remove_raw(tx, &key);
+        }
+        for (key, value) in self.fixes {
+            let key = (repository_id, key);
+            match value {
+                Some(value) => table.insert(tx, &key, &value)?,
+                None => table.remove(tx, &key)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares the entries of a repository in the given index with the expected
+/// ones, reports the differences in `issues` and returns their repairs.
+///
+/// Missing entries are considered to be [`Default`].
+fn check_index<K, V>(
+    table: &Table<(RepositoryId, K), V>,
+    tx: &impl ReadableTransaction,
+    repository_id: RepositoryId,
+    mut expected: HashMap<K, V>,
+    issue: fn(K) -> IntegrityIssue,
+    issues: &mut Vec<IntegrityIssue>,
+) -> Result<Repairs<K, V>, Error>
+where
+    K: Copy + Eq + Hash + BorshSerialize + BorshDeserialize,
+    V: Default + PartialEq + BorshSerialize + BorshDeserialize,
+{
+    expected.retain(|_, value| *value != V::default());
+    let mut repairs = Repairs::default();
+    for result in table.prefix_entries(tx, &repository_id)? {
+        let (raw_key, entry) = result?;
+        let Some(((_, key), stored)) = entry else {
+            issues.push(IntegrityIssue::Undecodable(table.name(), raw_key.to_vec()));
+            repairs.undecodable.push(raw_key);
+            continue;
+        };
+        match expected.remove(&key) {
+            Some(value) if value == stored => {}
+            None if stored == V::default() => {}
+            value => {
+                issues.push(issue(key));
+                repairs.fixes.push((key, value));
+            }
+        }
+    }
+    for (key, value) in expected {
+        issues.push(issue(key));
+        repairs.fixes.push((key, Some(value)));
+    }
+    Ok(repairs)
+}
+
+/// Returns `true` if a line whose existence has the given heads is stored in
+/// the lines table, which is the case if it exists or is in conflict.
+fn line_should_exist(heads: &HashSet<ChangeHash>, changes: &HashMap<ChangeHash, Change>) -> bool {
+    let mut existences = heads
+        .iter()
+        .filter_map(|head| match changes.get(head)?.content {
+            ChangeContent::LineExistence { existence, .. } => Some(existence),
+            ChangeContent::LineContent { .. }
+            | ChangeContent::LineParent { .. }
+            | ChangeContent::LineChild { .. } => None,
+        });
+    let Some(first) = existences.next() else {
+        return false;
+    };
+    first || existences.any(|existence| existence != first)
+}
+
+/// Converts an [`InvalidChange`] into an [Error].
+fn invalid_change(err: InvalidChange) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+/// Decodes a sequence number used as a key in the events partition.
+fn decode_sequence(key: &[u8]) -> Result<u64, Error> {
+    let Ok(bytes) = <[u8; 8]>::try_from(key) else {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid event sequence number",
+        )));
+    };
+    Ok(u64::from_be_bytes(bytes))
 }
 
 impl RepositoryManager for PersistentRepositoryManager {
@@ -88,9 +626,24 @@ impl RepositoryManager for PersistentRepositoryManager {
             transaction: RepositoryTransaction::Write(self.keyspace.write_tx()),
         })
     }
+
+    fn used_contents(&self) -> Result<HashSet<ContentHash>, Self::Error> {
+        let mut used = HashSet::new();
+        for result in self.changes.prefix(&self.keyspace.read_tx(), &())? {
+            let (_, change) = result?;
+            if let ChangeContent::LineContent { content, .. } = change.content {
+                used.insert(content);
+            }
+        }
+        Ok(used)
+    }
 }
 
 /// An enum that represents a read or a write transaction.
+#[expect(
+    clippy::large_enum_variant,
+    reason = "there is only one transaction per opened repository"
+)]
 enum RepositoryTransaction<'manager> {
     /// A read-only transaction.
     Read(ReadTransaction),
@@ -99,6 +652,30 @@ enum RepositoryTransaction<'manager> {
     Write(WriteTransaction<'manager>),
 }
 
+impl ReadableTransaction for RepositoryTransaction<'_> {
+    fn get_raw(
+        &self,
+        partition: &TransactionalPartitionHandle,
+        key: &[u8],
+    ) -> Result<Option<Slice>, Error> {
+        match *self {
+            RepositoryTransaction::Read(ref tx) => tx.get_raw(partition, key),
+            RepositoryTransaction::Write(ref tx) => tx.get_raw(partition, key),
+        }
+    }
+
+    fn prefix_raw<'tx>(
+        &'tx self,
+        partition: &'tx TransactionalPartitionHandle,
+        prefix: Vec<u8>,
+    ) -> Box<dyn Iterator<Item = Result<(Slice, Slice), Error>> + 'tx> {
+        match *self {
+            RepositoryTransaction::Read(ref tx) => tx.prefix_raw(partition, prefix),
+            RepositoryTransaction::Write(ref tx) => tx.prefix_raw(partition, prefix),
+        }
+    }
+}
+
 /// An implementation of the [Repository] trait that stores data in persistent
 /// storage (on disk).
 pub struct PersistentRepository<'manager> {
@@ -116,7 +693,7 @@ impl PersistentRepository<'_> {
     /// Updates the existence of a line in the repository.
     fn update_line(&mut self, file_id: FileId, line_id: LineId) -> Result<(), Error> {
         let existence = (self.line_existence(file_id, line_id)?).unwrap_or(true);
-        let key = borsh::to_vec(&(self.id, file_id, line_id))?;
+        let key = (self.id, file_id, line_id);
         let RepositoryTransaction::Write(ref mut tx) = self.transaction else {
             return Err(Error::Io(io::Error::new(
                 io::ErrorKind::ReadOnlyFilesystem,
@@ -124,11 +701,10 @@ impl PersistentRepository<'_> {
             )));
         };
         if existence {
-            tx.insert(&self.manager.lines, key, b"");
+            self.manager.lines.insert(tx, &key, &())
         } else {
-            tx.remove(&self.manager.lines, key);
+            self.manager.lines.remove(tx, &key)
         }
-        Ok(())
     }
 }
 
@@ -136,66 +712,42 @@ impl<'manager> Repository<'manager> for PersistentRepository<'manager> {
     type Error = Error;
 
     fn changes(&self) -> impl Iterator<Item = Result<(ChangeHash, Change), Self::Error>> {
-        let iter: Box<dyn Iterator<Item = Result<(Slice, Slice), _>>> = match self.transaction {
-            RepositoryTransaction::Read(ref tx) => {
-                Box::new(tx.prefix(&self.manager.changes, self.id.as_bytes()))
-            }
-            RepositoryTransaction::Write(ref tx) => {
-                Box::new(tx.prefix(&self.manager.changes, self.id.as_bytes()))
-            }
-        };
-
-        iter.map(|result| match result {
-            Ok((key, value)) => {
-                let (_, hash) = borsh::from_slice::<(RepositoryId, ChangeHash)>(&key)?;
-                let change = borsh::from_slice::<Change>(&value)?;
-                Ok((hash, change))
-            }
-            Err(err) => Err(err),
-        })
+        let changes: Box<dyn Iterator<Item = Result<_, _>>> =
+            match self.manager.changes.prefix(&self.transaction, &self.id) {
+                Ok(changes) => Box::new(changes),
+                Err(err) => Box::new(iter::once(Err(err))),
+            };
+        changes.map(|result| result.map(|((_, change_hash), change)| (change_hash, change)))
     }
 
     fn change(&self, change_hash: ChangeHash) -> Result<Option<Change>, Self::Error> {
-        let key = borsh::to_vec(&(self.id, change_hash))?;
-        let value = match self.transaction {
-            RepositoryTransaction::Read(ref tx) => tx.get(&self.manager.changes, key)?,
-            RepositoryTransaction::Write(ref tx) => tx.get(&self.manager.changes, key)?,
-        };
-        match value {
-            Some(value) => Ok(Some(borsh::from_slice(&value)?)),
-            None => Ok(None),
-        }
+        self.manager
+            .changes
+            .get(&self.transaction, &(self.id, change_hash))
     }
 
     fn heads(&self, single_id: SingleId) -> Result<HashSet<ChangeHash>, Self::Error> {
-        let key = borsh::to_vec(&(self.id, single_id))?;
-        let value = match self.transaction {
-            RepositoryTransaction::Read(ref tx) => tx.get(&self.manager.heads, key)?,
-            RepositoryTransaction::Write(ref tx) => tx.get(&self.manager.heads, key)?,
-        };
-        match value {
-            Some(value) => Ok(borsh::from_slice(&value)?),
-            None => Ok(HashSet::new()),
-        }
+        Ok(self
+            .manager
+            .heads
+            .get(&self.transaction, &(self.id, single_id))?
+            .unwrap_or_default())
     }
 
     fn existing_lines(&self, file_id: FileId) -> Result<HashSet<LineId>, Self::Error> {
-        let prefix = borsh::to_vec(&(self.id, file_id))?;
-        let iter: Box<dyn Iterator<Item = Result<(Slice, Slice), _>>> = match self.transaction {
-            RepositoryTransaction::Read(ref tx) => Box::new(tx.prefix(&self.manager.lines, prefix)),
-            RepositoryTransaction::Write(ref tx) => {
-                Box::new(tx.prefix(&self.manager.lines, prefix))
-            }
-        };
+        self.manager
+            .lines
+            .prefix(&self.transaction, &(self.id, file_id))?
+            .map(|result| result.map(|((_, _, line_id), ())| line_id))
+            .collect()
+    }
 
-        iter.map(|result| match result {
-            Ok((key, _)) => {
-                let (_, _, line_id) = borsh::from_slice::<(RepositoryId, FileId, LineId)>(&key)?;
-                Ok(line_id)
-            }
-            Err(err) => Err(err),
-        })
-        .collect()
+    fn files(&self) -> Result<HashSet<FileId>, Self::Error> {
+        self.manager
+            .files
+            .prefix(&self.transaction, &self.id)?
+            .map(|result| result.map(|((_, file_id), _)| file_id))
+            .collect()
     }
 
     fn apply(&mut self, change: Change) -> Result<ChangeHash, Self::Error> {
@@ -205,57 +757,75 @@ impl<'manager> Repository<'manager> for PersistentRepository<'manager> {
                 "cannot apply changes to read-only transaction",
             )));
         };
+        let manager = self.manager;
 
-        // Insert the change
+        // Check that the change is valid
+        change.validate().map_err(invalid_change)?;
         let change_hash = change.calculate_hash();
-        tx.insert(
-            &self.manager.changes,
-            borsh::to_vec(&(self.id, change_hash))?,
-            borsh::to_vec(&change)?,
-        );
+        for replaced_hash in change.replace {
+            if let Some(replaced) = manager.changes.get(tx, &(self.id, replaced_hash))? {
+                if replaced.single_id() != change.single_id() {
+                    return Err(invalid_change(InvalidChange::ForeignReplacement(
+                        replaced_hash,
+                    )));
+                }
+            }
+        }
+        let replacing_hashes = manager
+            .reverse_heads
+            .get(tx, &(self.id, change_hash))?
+            .unwrap_or_default();
+        for replacing_hash in replacing_hashes {
+            if let Some(replacing) = manager.changes.get(tx, &(self.id, replacing_hash))? {
+                if replacing.single_id() != change.single_id() {
+                    return Err(invalid_change(InvalidChange::ForeignReplacement(
+                        replacing_hash,
+                    )));
+                }
+            }
+        }
+
+        // Insert the change
+        let change_key = (self.id, change_hash);
+        if !manager.changes.contains_key(tx, &change_key)? {
+            if manager.changes.prefix(tx, &self.id)?.next().is_none() {
+                manager.record_event(tx, RepositoryEvent::RepositoryCreated(self.id))?;
+            }
+            manager.record_event(tx, RepositoryEvent::ChangeApplied(self.id, change_hash))?;
+            manager.count_file_change(tx, self.id, change.file_id(), true)?;
+        }
+        manager.changes.insert(tx, &change_key, &change)?;
 
         // Update the reversed heads
         for replaced_hash in change.replace {
-            let serialized_key = borsh::to_vec(&(self.id, replaced_hash))?;
-
             // Get all changes that replace this change
-            let reverse_heads = tx.get(&self.manager.reverse_heads, &serialized_key)?;
-            let mut reverse_heads = match reverse_heads {
-                Some(reverse_heads) => borsh::from_slice(&reverse_heads)?,
-                None => HashSet::new(),
-            };
+            let replaced_key = (self.id, replaced_hash);
+            let mut reverse_heads = manager
+                .reverse_heads
+                .get(tx, &replaced_key)?
+                .unwrap_or_default();
 
             // Update the reversed heads by adding this change
             reverse_heads.insert(change_hash);
-            tx.insert(
-                &self.manager.reverse_heads,
-                serialized_key,
-                borsh::to_vec(&reverse_heads)?,
-            );
+            manager
+                .reverse_heads
+                .insert(tx, &replaced_key, &reverse_heads)?;
         }
 
         // Update the heads
-        let single_key = borsh::to_vec(&(self.id, change.single_id()))?;
-        let heads = tx.get(&self.manager.heads, &single_key)?;
-        let mut heads = match heads {
-            Some(heads) => borsh::from_slice(&heads)?,
-            None => HashSet::new(),
-        };
+        let single_key = (self.id, change.single_id());
+        let mut heads = manager.heads.get(tx, &single_key)?.unwrap_or_default();
         for change_hash in change.replace {
             heads.remove(&change_hash);
         }
-        let reverse_heads = tx.get(
-            &self.manager.reverse_heads,
-            borsh::to_vec(&(self.id, change_hash))?,
-        )?;
-        let reverse_heads: HashSet<ChangeHash> = match reverse_heads {
-            Some(reverse_heads) => borsh::from_slice(&reverse_heads)?,
-            None => HashSet::new(),
-        };
+        let reverse_heads = manager
+            .reverse_heads
+            .get(tx, &change_key)?
+            .unwrap_or_default();
         if reverse_heads.is_empty() {
             heads.insert(change_hash);
         }
-        tx.insert(&self.manager.heads, single_key, borsh::to_vec(&heads)?);
+        manager.heads.insert(tx, &single_key, &heads)?;
 
         // Update the line existence if needed
         if let ChangeContent::LineExistence {
@@ -276,50 +846,45 @@ impl<'manager> Repository<'manager> for PersistentRepository<'manager> {
                 "cannot unapply changes to read-only transaction",
             )));
         };
+        let manager = self.manager;
 
         // Remove the change
-        let Some(change) = tx.take(
-            &self.manager.changes,
-            borsh::to_vec(&(self.id, change_hash))?,
-        )?
-        else {
+        let Some(change) = manager.changes.take(tx, &(self.id, change_hash))? else {
             return Ok(());
         };
-        let change: Change = borsh::from_slice(&change)?;
+        manager.record_event(tx, RepositoryEvent::ChangeUnapplied(self.id, change_hash))?;
+        manager.count_file_change(tx, self.id, change.file_id(), false)?;
 
         // Update the heads
-        let single_key = borsh::to_vec(&(self.id, change.single_id()))?;
-        let heads = tx.get(&self.manager.heads, &single_key)?;
-        let mut heads = match heads {
-            Some(heads) => borsh::from_slice(&heads)?,
-            None => HashSet::new(),
-        };
+        let single_key = (self.id, change.single_id());
+        let mut heads = manager.heads.get(tx, &single_key)?.unwrap_or_default();
         heads.remove(&change_hash);
         for replaced_hash in change.replace {
-            let serialized_key = borsh::to_vec(&(self.id, replaced_hash))?;
+            let replaced_key = (self.id, replaced_hash);
 
             // Verify that the replaced change is replaced ONLY by this change
-            let Some(reverse_heads) = tx.get(&self.manager.reverse_heads, &serialized_key)? else {
+            let Some(mut reverse_heads) = manager.reverse_heads.get(tx, &replaced_key)? else {
                 continue;
             };
-            let mut reverse_heads: HashSet<ChangeHash> = borsh::from_slice(&reverse_heads)?;
-            if reverse_heads.len() == 1 && reverse_heads.contains(&change_hash) {
-                // Add the replaced change to the heads
+            if reverse_heads.len() == 1
+                && reverse_heads.contains(&change_hash)
+                && manager.changes.contains_key(tx, &replaced_key)?
+            {
+                // Add the replaced change to the heads if it is applied
                 heads.insert(replaced_hash);
             }
 
             // Update the replaced change by removing this change
             reverse_heads.remove(&change_hash);
             if reverse_heads.is_empty() {
-                tx.remove(&self.manager.reverse_heads, &serialized_key);
+                manager.reverse_heads.remove(tx, &replaced_key)?;
+            } else {
+                manager
+                    .reverse_heads
+                    .insert(tx, &replaced_key, &reverse_heads)?;
             }
-            tx.insert(
-                &self.manager.reverse_heads,
-                serialized_key,
-                borsh::to_vec(&reverse_heads)?,
-            );
         }
-        tx.insert(&self.manager.heads, single_key, borsh::to_vec(&heads)?);
+        manager.heads.insert(tx, &single_key, &heads)?;
 
         // Update the line existence if needed
         if let ChangeContent::LineExistence {
@@ -0,0 +1,211 @@
+//! Defines a typed [Table] over a fjall partition.
+
+use core::marker::PhantomData;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use fjall::{
+    Error, PartitionCreateOptions, ReadTransaction, Slice, TransactionalKeyspace,
+    TransactionalPartitionHandle, WriteTransaction,
+};
+
+/// A transaction that can be used to read from a [Table].
+pub trait ReadableTransaction {
+    /// Returns the raw value of the given raw key in the partition.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the value could not be read.
+    fn get_raw(
+        &self,
+        partition: &TransactionalPartitionHandle,
+        key: &[u8],
+    ) -> Result<Option<Slice>, Error>;
+
+    /// Returns an [Iterator] over the raw key-value pairs of the partition
+    /// whose key starts with the given raw prefix.
+    fn prefix_raw<'tx>(
+        &'tx self,
+        partition: &'tx TransactionalPartitionHandle,
+        prefix: Vec<u8>,
+    ) -> Box<dyn Iterator<Item = Result<(Slice, Slice), Error>> + 'tx>;
+}
+
+impl ReadableTransaction for ReadTransaction {
+    fn get_raw(
+        &self,
+        partition: &TransactionalPartitionHandle,
+        key: &[u8],
+    ) -> Result<Option<Slice>, Error> {
+        self.get(partition, key)
+    }
+
+    fn prefix_raw<'tx>(
+        &'tx self,
+        partition: &'tx TransactionalPartitionHandle,
+        prefix: Vec<u8>,
+    ) -> Box<dyn Iterator<Item = Result<(Slice, Slice), Error>> + 'tx> {
+        Box::new(self.prefix(partition, prefix))
+    }
+}
+
+impl ReadableTransaction for WriteTransaction<'_> {
+    fn get_raw(
+        &self,
+        partition: &TransactionalPartitionHandle,
+        key: &[u8],
+    ) -> Result<Option<Slice>, Error> {
+        self.get(partition, key)
+    }
+
+    fn prefix_raw<'tx>(
+        &'tx self,
+        partition: &'tx TransactionalPartitionHandle,
+        prefix: Vec<u8>,
+    ) -> Box<dyn Iterator<Item = Result<(Slice, Slice), Error>> + 'tx> {
+        Box::new(self.prefix(partition, prefix))
+    }
+}
+
+/// The raw key of an entry of a [Table] along with the entry, or `None` if it
+/// could not be decoded.
+pub type RawEntry<K, V> = (Slice, Option<(K, V)>);
+
+/// A fjall partition whose keys and values are borsh encoded.
+///
+/// Borsh encodes tuples field by field, so all the keys starting with the
+/// same fields can be iterated over with [`Table::prefix`].
+pub struct Table<K, V> {
+    /// The name of the partition.
+    name: &'static str,
+
+    /// The partition storing the encoded keys and values.
+    partition: TransactionalPartitionHandle,
+
+    /// The types of the keys and values.
+    types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: BorshSerialize + BorshDeserialize, V: BorshSerialize + BorshDeserialize> Table<K, V> {
+    /// Opens the partition with the given name as a [Table], creating it if
+    /// it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the partition could not be opened.
+    pub fn open(keyspace: &TransactionalKeyspace, name: &'static str) -> Result<Self, Error> {
+        Ok(Self {
+            name,
+            partition: keyspace.open_partition(name, PartitionCreateOptions::default())?,
+            types: PhantomData,
+        })
+    }
+
+    /// Returns the name of the partition of this table.
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the value associated with the given key.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the value could not be read or decoded.
+    pub fn get(&self, tx: &impl ReadableTransaction, key: &K) -> Result<Option<V>, Error> {
+        match tx.get_raw(&self.partition, &borsh::to_vec(key)?)? {
+            Some(value) => Ok(Some(borsh::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns `true` if the table contains the given key.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the table could not be read.
+    pub fn contains_key(&self, tx: &WriteTransaction, key: &K) -> Result<bool, Error> {
+        tx.contains_key(&self.partition, borsh::to_vec(key)?)
+    }
+
+    /// Returns an [Iterator] over the entries of the table whose encoded key
+    /// starts with the encoding of the given prefix.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the prefix could not be encoded.
+    pub fn prefix<'tx>(
+        &'tx self,
+        tx: &'tx impl ReadableTransaction,
+        prefix: &impl BorshSerialize,
+    ) -> Result<impl Iterator<Item = Result<(K, V), Error>> + 'tx, Error> {
+        Ok(tx
+            .prefix_raw(&self.partition, borsh::to_vec(prefix)?)
+            .map(|result| {
+                let (key, value) = result?;
+                Ok((borsh::from_slice(&key)?, borsh::from_slice(&value)?))
+            }))
+    }
+
+    /// Returns an [Iterator] over the entries of the table whose encoded key
+    /// starts with the encoding of the given prefix, along with their raw
+    /// key.
+    ///
+    /// Unlike [`Table::prefix`], an entry that could not be decoded is
+    /// returned as `None` instead of stopping the iteration.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the prefix could not be encoded.
+    pub fn prefix_entries<'tx>(
+        &'tx self,
+        tx: &'tx impl ReadableTransaction,
+        prefix: &impl BorshSerialize,
+    ) -> Result<impl Iterator<Item = Result<RawEntry<K, V>, Error>> + 'tx, Error> {
+        Ok(tx
+            .prefix_raw(&self.partition, borsh::to_vec(prefix)?)
+            .map(|result| {
+                let (key, value) = result?;
+                let entry = borsh::from_slice(&key)
+                    .and_then(|key| Ok((key, borsh::from_slice(&value)?)))
+                    .ok();
+                Ok((key, entry))
+            }))
+    }
+
+    /// Associates the given value with the given key.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the key or the value could not be
+    /// encoded.
+    pub fn insert(&self, tx: &mut WriteTransaction, key: &K, value: &V) -> Result<(), Error> {
+        tx.insert(&self.partition, borsh::to_vec(key)?, borsh::to_vec(value)?);
+        Ok(())
+    }
+
+    /// Removes the given key from the table.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the key could not be encoded.
+    pub fn remove(&self, tx: &mut WriteTransaction, key: &K) -> Result<(), Error> {
+        tx.remove(&self.partition, borsh::to_vec(key)?);
+        Ok(())
+    }
+
+    /// Removes the given raw key from the table.
+    pub fn remove_raw(&self, tx: &mut WriteTransaction, key: &[u8]) {
+        tx.remove(&self.partition, key);
+    }
+
+    /// Removes the given key from the table and returns its previous value.
+    ///
+    /// # Errors
+    ///
+    /// An error will be returned if the value could not be read or decoded.
+    pub fn take(&self, tx: &mut WriteTransaction, key: &K) -> Result<Option<V>, Error> {
+        match tx.take(&self.partition, borsh::to_vec(key)?)? {
+            Some(value) => Ok(Some(borsh::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+}
@@ -380,7 +380,7 @@ impl<T: Copy, const MAX: usize> StackVec<T, MAX> {
     /// assert_eq!(iter.next(), Some(5));
     /// assert_eq!(iter.next(), None);
     /// ```
-    pub const fn iter(&self) -> StackVecIter<T, MAX> {
+    pub const fn iter(&self) -> StackVecIter<'_, T, MAX> {
         StackVecIter {
             vec: self,
             index: 0,
@@ -409,7 +409,6 @@ impl<T: Copy, const MAX: usize> From<[T; MAX]> for StackVec<T, MAX> {
 }
 
 impl<T: Copy + Debug, const MAX: usize> Debug for StackVec<T, MAX> {
-    #[expect(clippy::min_ident_chars, reason = "the trait is made that way")]
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_list()
             .entries((0..self.len).map(|i| {
@@ -0,0 +1,292 @@
+//! Tests on [Bundle]
+
+use std::io::Read;
+
+use solipr_core::bundle::{Bundle, BundleError, BundleReadError, MAGIC, VERSION};
+use solipr_core::change::{Change, ChangeContent, FileId, LineId};
+use solipr_core::registry::{ContentHash, Registry};
+use solipr_core::repository::{Repository, RepositoryId, RepositoryManager};
+use solipr_memory::registry::MemoryRegistry;
+use solipr_persistent::repository::PersistentRepositoryManager;
+use solipr_stack::StackVec;
+use tempfile::TempDir;
+
+fn random_file_id() -> FileId {
+    format!("{:032x}", rand::random::<u128>()).parse().unwrap()
+}
+
+fn random_line_id() -> LineId {
+    format!("{:032x}", rand::random::<u128>()).parse().unwrap()
+}
+
+#[test]
+fn bundle_round_trip() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let registry = MemoryRegistry::new();
+    let source_id = RepositoryId::create_new();
+
+    // Create a repository with a line and its content
+    let file_id = random_file_id();
+    let line_id = random_line_id();
+    let content = registry.write(b"hello".as_slice()).unwrap();
+    let mut source = manager.open_write(source_id).unwrap();
+    for content in [
+        ChangeContent::LineExistence {
+            file_id,
+            line_id,
+            existence: true,
+        },
+        ChangeContent::LineContent {
+            file_id,
+            line_id,
+            content,
+        },
+    ] {
+        source
+            .apply(Change {
+                replace: StackVec::new(),
+                content,
+            })
+            .unwrap();
+    }
+    source.commit().unwrap();
+
+    // The bundle contains the changes and the used contents
+    let source = manager.open_read(source_id).unwrap();
+    let bundle = Bundle::collect(&source, &registry, None).unwrap();
+    assert_eq!(
+        bundle.changes().len(),
+        2,
+        "the bundle should contain all the changes"
+    );
+    assert_eq!(
+        bundle.contents().collect::<Vec<_>>(),
+        vec![(content, b"hello".as_slice())],
+        "the bundle should contain the used contents"
+    );
+    drop(source);
+
+    // The bundle survives being written and read back
+    let mut data = Vec::new();
+    bundle.write(&mut data).unwrap();
+    let read_bundle = Bundle::read(data.as_slice()).unwrap();
+    assert_eq!(
+        read_bundle, bundle,
+        "the bundle should be read back unchanged"
+    );
+
+    // Applying the bundle copies the changes and the contents
+    let other_registry = MemoryRegistry::new();
+    let mut target = manager.open_write(RepositoryId::create_new()).unwrap();
+    read_bundle.apply(&mut target, &other_registry).unwrap();
+    for change in bundle.changes() {
+        assert_eq!(
+            target.change(change.calculate_hash()).unwrap(),
+            Some(*change),
+            "the changes of the bundle should be applied"
+        );
+    }
+    let mut buffer = Vec::new();
+    other_registry
+        .read(content)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buffer)
+        .unwrap();
+    assert_eq!(buffer, b"hello", "the contents should be written");
+    drop(target);
+
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn bundle_integrity_checks() {
+    let mut bundle = Bundle::new();
+    bundle.add_content(b"hello".to_vec());
+    let mut data = Vec::new();
+    bundle.write(&mut data).unwrap();
+
+    let mut corrupted = data.clone();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 1;
+    assert!(
+        matches!(
+            Bundle::read(corrupted.as_slice()),
+            Err(BundleReadError::Corrupted)
+        ),
+        "a corrupted bundle should be rejected"
+    );
+
+    let mut other_version = data.clone();
+    other_version[MAGIC.len()] = VERSION + 1;
+    assert!(
+        matches!(
+            Bundle::read(other_version.as_slice()),
+            Err(BundleReadError::UnsupportedVersion(_))
+        ),
+        "an unknown version should be rejected"
+    );
+
+    assert!(
+        matches!(
+            Bundle::read(b"hello".as_slice()),
+            Err(BundleReadError::NotABundle)
+        ),
+        "data that is not a bundle should be rejected"
+    );
+}
+
+#[test]
+fn bundle_since() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let registry = MemoryRegistry::new();
+    let repository_id = RepositoryId::create_new();
+
+    // Write a line, replace its content and add another line
+    let file_id = random_file_id();
+    let line_id = random_line_id();
+    let hello = registry.write(b"hello".as_slice()).unwrap();
+    let world = registry.write(b"world".as_slice()).unwrap();
+    let mut repository = manager.open_write(repository_id).unwrap();
+    let first = repository
+        .apply(Change {
+            replace: StackVec::new(),
+            content: ChangeContent::LineContent {
+                file_id,
+                line_id,
+                content: hello,
+            },
+        })
+        .unwrap();
+    let mut replace = StackVec::new();
+    replace.push(first);
+    let second = Change {
+        replace,
+        content: ChangeContent::LineContent {
+            file_id,
+            line_id,
+            content: world,
+        },
+    };
+    let second_hash = repository.apply(second).unwrap();
+    let other = Change {
+        replace: StackVec::new(),
+        content: ChangeContent::LineExistence {
+            file_id,
+            line_id: random_line_id(),
+            existence: true,
+        },
+    };
+    repository.apply(other).unwrap();
+    repository.commit().unwrap();
+
+    // The given change and the changes it replaces are left out
+    let repository = manager.open_read(repository_id).unwrap();
+    let bundle = Bundle::collect(&repository, &registry, Some(first)).unwrap();
+    let mut changes = bundle.changes().to_vec();
+    changes.sort_unstable_by_key(Change::calculate_hash);
+    let mut expected = vec![second, other];
+    expected.sort_unstable_by_key(Change::calculate_hash);
+    assert_eq!(
+        changes, expected,
+        "only the changes following the given change should be collected"
+    );
+    assert_eq!(
+        bundle.contents().collect::<Vec<_>>(),
+        vec![(world, b"world".as_slice())],
+        "only the contents of the collected changes should be collected"
+    );
+
+    let bundle = Bundle::collect(&repository, &registry, Some(second_hash)).unwrap();
+    assert_eq!(
+        bundle.changes(),
+        [other],
+        "the changes replaced indirectly should be left out"
+    );
+    assert_eq!(
+        bundle.contents().count(),
+        0,
+        "the contents of the left out changes should not be collected"
+    );
+
+    // The given change must be applied to the repository
+    let unknown = Change {
+        replace: StackVec::new(),
+        content: ChangeContent::LineExistence {
+            file_id,
+            line_id: random_line_id(),
+            existence: true,
+        },
+    }
+    .calculate_hash();
+    assert!(
+        matches!(
+            Bundle::collect(&repository, &registry, Some(unknown)),
+            Err(BundleError::ChangeNotFound(change_hash)) if change_hash == unknown
+        ),
+        "an unknown change should be rejected"
+    );
+    drop(repository);
+
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn bundle_missing_content() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let registry = MemoryRegistry::new();
+    let content = ContentHash::new(rand::random());
+    let mut bundle = Bundle::new();
+    bundle.add_content(b"unused".to_vec());
+    bundle.add_change(Change {
+        replace: StackVec::new(),
+        content: ChangeContent::LineContent {
+            file_id: random_file_id(),
+            line_id: random_line_id(),
+            content,
+        },
+    });
+
+    // A bundle using a content it does not provide is rejected
+    let mut target = manager.open_write(RepositoryId::create_new()).unwrap();
+    assert!(
+        matches!(
+            bundle.apply(&mut target, &registry),
+            Err(BundleError::NotFound(hash)) if hash == content
+        ),
+        "a change using a missing content should be rejected"
+    );
+    assert_eq!(target.changes().count(), 0, "no change should be applied");
+    assert!(
+        registry.contents().unwrap().is_empty(),
+        "no content should be written"
+    );
+    drop(target);
+
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn bundle_contents_are_deduplicated() {
+    let mut bundle = Bundle::new();
+    let hello = bundle.add_content(b"hello".to_vec());
+    let world = bundle.add_content(b"world".to_vec());
+    assert_eq!(
+        bundle.add_content(b"hello".to_vec()),
+        hello,
+        "the hash of the content should be returned"
+    );
+    let mut expected = vec![(hello, b"hello".as_slice()), (world, b"world".as_slice())];
+    expected.sort_unstable();
+    assert_eq!(
+        bundle.contents().collect::<Vec<_>>(),
+        expected,
+        "each content should be stored once, sorted by hash"
+    );
+}
@@ -2,4 +2,9 @@
 
 #![cfg(test)]
 
+mod bundle;
+mod linear;
+mod migration;
 mod registry;
+mod repository;
+mod wire_format;
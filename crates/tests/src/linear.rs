@@ -0,0 +1,34 @@
+//! Tests on [`LinearFile`]
+
+use std::sync::Arc;
+
+use solipr_core::repository::linear::LinearFile;
+use solipr_memory::registry::MemoryRegistry;
+
+fn round_trip(content: &[u8]) {
+    let registry = Arc::new(MemoryRegistry::new());
+    let file = LinearFile::parse::<MemoryRegistry>(&registry, content)
+        .unwrap_or_else(|err| panic!("could not parse the file: {err}"));
+    let mut rendered = Vec::new();
+    file.render::<MemoryRegistry>(&registry, &mut rendered)
+        .unwrap_or_else(|err| panic!("could not render the file: {err}"));
+    assert_eq!(
+        rendered,
+        content,
+        "rendering a parsed file should give back {:?}",
+        String::from_utf8_lossy(content)
+    );
+}
+
+#[test]
+fn empty_file_round_trip() {
+    round_trip(b"");
+}
+
+#[test]
+fn empty_lines_round_trip() {
+    round_trip(b"\n");
+    round_trip(b"\n\n");
+    round_trip(b"a\n\nb");
+    round_trip(b"a\n");
+}
@@ -0,0 +1,293 @@
+//! Tests on the migrations of the on-disk format
+
+use std::collections::HashSet;
+
+use fjall::{Config, Error, PartitionCreateOptions, TransactionalKeyspace};
+use solipr_core::change::{Change, ChangeContent, FileId};
+use solipr_core::repository::{Repository, RepositoryId, RepositoryManager};
+use solipr_persistent::migration::{self, CURRENT_VERSION, MIGRATION_BATCH_SIZE, Migration};
+use solipr_persistent::repository::PersistentRepositoryManager;
+use solipr_stack::StackVec;
+use tempfile::TempDir;
+
+/// Writes the given version into the database in the given folder.
+fn set_version(temp_dir: &TempDir, version: u32) {
+    let keyspace = Config::new(temp_dir.path()).open_transactional().unwrap();
+    let meta = keyspace
+        .open_partition("meta", PartitionCreateOptions::default())
+        .unwrap();
+    let mut tx = keyspace.write_tx();
+    tx.insert(
+        &meta,
+        borsh::to_vec("schema_version").unwrap(),
+        borsh::to_vec(&version).unwrap(),
+    );
+    tx.commit().unwrap();
+}
+
+/// Appends the given step to the `steps` partition of the database.
+///
+/// If `fail` is `true`, an error is returned instead of committing the step.
+fn record_step(keyspace: &TransactionalKeyspace, step: u8, fail: bool) -> Result<(), Error> {
+    let steps = keyspace.open_partition("steps", PartitionCreateOptions::default())?;
+    let mut tx = keyspace.write_tx();
+    let mut recorded = tx
+        .get(&steps, "steps")?
+        .map(|slice| slice.to_vec())
+        .unwrap_or_default();
+    recorded.push(step);
+    tx.insert(&steps, "steps", recorded);
+    if fail {
+        return Err(Error::Io(std::io::Error::other("migration failed")));
+    }
+    tx.commit()
+}
+
+/// Returns the steps recorded by [`record_step`].
+fn recorded_steps(keyspace: &TransactionalKeyspace) -> Vec<u8> {
+    let steps = keyspace
+        .open_partition("steps", PartitionCreateOptions::default())
+        .unwrap();
+    keyspace
+        .read_tx()
+        .get(&steps, "steps")
+        .unwrap()
+        .map(|slice| slice.to_vec())
+        .unwrap_or_default()
+}
+
+fn random_file_id() -> FileId {
+    format!("{:032x}", rand::random::<u128>()).parse().unwrap()
+}
+
+fn random_line_existence(file_id: FileId) -> Change {
+    Change {
+        replace: StackVec::new(),
+        content: ChangeContent::LineExistence {
+            file_id,
+            line_id: format!("{:032x}", rand::random::<u128>()).parse().unwrap(),
+            existence: true,
+        },
+    }
+}
+
+/// Removes the version and the partitions added since from the database in
+/// the given folder, like in databases created before the version was stored.
+fn remove_versioning(temp_dir: &TempDir) {
+    let keyspace = Config::new(temp_dir.path()).open_transactional().unwrap();
+    for name in ["meta", "files"] {
+        let partition = keyspace
+            .open_partition(name, PartitionCreateOptions::default())
+            .unwrap();
+        keyspace.delete_partition(partition).unwrap();
+    }
+    assert_eq!(
+        migration::schema_version(&keyspace).unwrap(),
+        None,
+        "the version should be removed"
+    );
+}
+
+const TEST_MIGRATIONS: &[Migration] = &[
+    |keyspace| record_step(keyspace, 1, false),
+    |keyspace| record_step(keyspace, 2, false),
+];
+
+#[test]
+fn new_database_is_current() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    drop(manager);
+
+    let keyspace = Config::new(temp_dir.path()).open_transactional().unwrap();
+    assert_eq!(
+        migration::schema_version(&keyspace).unwrap(),
+        Some(CURRENT_VERSION),
+        "a new database should be at the current version"
+    );
+    drop(keyspace);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn newer_database_is_refused() {
+    let temp_dir = TempDir::new().unwrap();
+    set_version(&temp_dir, CURRENT_VERSION + 1);
+    assert!(
+        PersistentRepositoryManager::create(temp_dir.path()).is_err(),
+        "a database written by a newer version should be refused"
+    );
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn unversioned_database_keeps_its_data() {
+    let temp_dir = TempDir::new().unwrap();
+    let repository_id = RepositoryId::create_new();
+
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let mut repository = manager.open_write(repository_id).unwrap();
+    let change = random_line_existence(random_file_id());
+    let change_hash = repository.apply(change).unwrap();
+    repository.commit().unwrap();
+    drop(manager);
+    remove_versioning(&temp_dir);
+
+    // The database is migrated and still contains the repository
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let repository = manager.open_read(repository_id).unwrap();
+    assert_eq!(
+        repository.change(change_hash).unwrap(),
+        Some(change),
+        "the data should survive the migration"
+    );
+    assert_eq!(
+        repository.files().unwrap(),
+        HashSet::from([change.file_id()]),
+        "the files should be indexed by the migration"
+    );
+    drop(repository);
+    drop(manager);
+    let keyspace = Config::new(temp_dir.path()).open_transactional().unwrap();
+    assert_eq!(
+        migration::schema_version(&keyspace).unwrap(),
+        Some(CURRENT_VERSION),
+        "the database should be migrated to the current version"
+    );
+    drop(keyspace);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn unversioned_database_indexes_files_in_batches() {
+    let temp_dir = TempDir::new().unwrap();
+    let large_id = RepositoryId::create_new();
+    let small_id = RepositoryId::create_new();
+
+    // Write more files than a single batch, and a repository after them
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let mut repository = manager.open_write(large_id).unwrap();
+    let mut large_files = HashSet::new();
+    for _ in 0..=MIGRATION_BATCH_SIZE {
+        let change = random_line_existence(random_file_id());
+        large_files.insert(change.file_id());
+        repository.apply(change).unwrap();
+    }
+    repository.commit().unwrap();
+    let small_file = random_file_id();
+    let mut repository = manager.open_write(small_id).unwrap();
+    repository.apply(random_line_existence(small_file)).unwrap();
+    repository.apply(random_line_existence(small_file)).unwrap();
+    repository.commit().unwrap();
+    drop(manager);
+    remove_versioning(&temp_dir);
+
+    // All the files are indexed by the migration
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    assert_eq!(
+        manager.open_read(large_id).unwrap().files().unwrap(),
+        large_files,
+        "the files of every batch should be indexed"
+    );
+    assert_eq!(
+        manager.open_read(small_id).unwrap().files().unwrap(),
+        HashSet::from([small_file]),
+        "the files of every repository should be indexed"
+    );
+    let mut repository = manager.open_write(small_id).unwrap();
+    for (change_hash, _) in repository.changes().collect::<Result<Vec<_>, _>>().unwrap() {
+        repository.unapply(change_hash).unwrap();
+    }
+    assert!(
+        repository.files().unwrap().is_empty(),
+        "the files should be counted once per change"
+    );
+    drop(repository);
+
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn migrations_run_in_order_once() {
+    let temp_dir = TempDir::new().unwrap();
+    set_version(&temp_dir, 1);
+    let keyspace = Config::new(temp_dir.path()).open_transactional().unwrap();
+
+    assert_eq!(
+        migration::migrate_with(&keyspace, TEST_MIGRATIONS).unwrap(),
+        1,
+        "the previous version should be returned"
+    );
+    assert_eq!(
+        recorded_steps(&keyspace),
+        [1, 2],
+        "the migrations should run in order"
+    );
+    assert_eq!(
+        migration::schema_version(&keyspace).unwrap(),
+        Some(3),
+        "the database should be at the version following the last migration"
+    );
+
+    assert_eq!(
+        migration::migrate_with(&keyspace, TEST_MIGRATIONS).unwrap(),
+        3,
+        "the database should already be migrated"
+    );
+    assert_eq!(
+        recorded_steps(&keyspace),
+        [1, 2],
+        "the migrations should only run once"
+    );
+
+    drop(keyspace);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn migrations_resume_from_stored_version() {
+    let temp_dir = TempDir::new().unwrap();
+    set_version(&temp_dir, 2);
+    let keyspace = Config::new(temp_dir.path()).open_transactional().unwrap();
+
+    migration::migrate_with(&keyspace, TEST_MIGRATIONS).unwrap();
+    assert_eq!(
+        recorded_steps(&keyspace),
+        [2],
+        "only the missing migrations should run"
+    );
+
+    drop(keyspace);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn failed_migration_keeps_previous_version() {
+    const FAILING_MIGRATIONS: &[Migration] = &[
+        |keyspace| record_step(keyspace, 1, false),
+        |keyspace| record_step(keyspace, 2, true),
+    ];
+
+    let temp_dir = TempDir::new().unwrap();
+    set_version(&temp_dir, 1);
+    let keyspace = Config::new(temp_dir.path()).open_transactional().unwrap();
+
+    assert!(
+        migration::migrate_with(&keyspace, FAILING_MIGRATIONS).is_err(),
+        "the failure of a migration should be returned"
+    );
+    assert_eq!(
+        recorded_steps(&keyspace),
+        [1],
+        "the writes of the failed migration should not be committed"
+    );
+    assert_eq!(
+        migration::schema_version(&keyspace).unwrap(),
+        Some(2),
+        "the database should stay at the version of the last successful migration"
+    );
+
+    drop(keyspace);
+    temp_dir.close().unwrap();
+}
@@ -1,11 +1,25 @@
 //! Tests on [Registry]
 
-use std::io::Read;
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::{self, Read};
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+use std::{fs, thread};
 
-use solipr_core::registry::{ContentHash, Registry};
+use solipr_core::registry::{AsyncRegistry, ContentHash, Registry, ResolveContentError};
 use solipr_memory::registry::MemoryRegistry;
-use solipr_persistent::registry::PersistentRegistry;
+use solipr_persistent::registry::{AsyncPersistentRegistry, PersistentRegistry};
 use tempfile::TempDir;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+use tokio::task::JoinSet;
+use tokio::time::Sleep;
+
+const WRITE_SLOTS: NonZeroUsize = NonZeroUsize::new(4).unwrap();
 
 fn registry_checks(registry: impl Registry) {
     for _ in 0..1024 {
@@ -23,12 +37,27 @@ fn registry_checks(registry: impl Registry) {
         b"world",
         "content:SG6kYiTRu0-2gPNPfJrZao8k7Ii-c-qOWmxlJg6cuKc",
     );
+
+    read_a_written_value(
+        &registry,
+        b"",
+        "content:47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU",
+    );
 }
 
 fn read_a_non_written_value(registry: &impl Registry) {
     let random_hash = ContentHash::new(rand::random());
     let read_content = registry.read(random_hash).unwrap();
     assert!(read_content.is_none(), "the content should not be found");
+    assert_eq!(
+        registry.size(random_hash).unwrap(),
+        None,
+        "the content should have no size"
+    );
+    assert!(
+        !registry.exists(random_hash).unwrap(),
+        "the content should not exist"
+    );
 }
 
 fn read_a_written_value(registry: &impl Registry, value: &[u8], expected_hash: &str) {
@@ -42,6 +71,304 @@ fn read_a_written_value(registry: &impl Registry, value: &[u8], expected_hash: &
     let mut buffer = Vec::new();
     read_content.read_to_end(&mut buffer).unwrap();
     assert_eq!(buffer, value, "the content should not change");
+    assert_eq!(
+        registry.size(hash).unwrap(),
+        Some(value.len() as u64),
+        "the size should be the length of the content"
+    );
+    assert!(registry.exists(hash).unwrap(), "the content should exist");
+}
+
+fn max_content_size_checks(registry: &impl Registry) {
+    let err = registry.write(&b"hello"[..]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "the content is bigger than the maximum size of 4 bytes",
+        "a content bigger than the limit should be rejected"
+    );
+    let hash = registry.write(&b"hell"[..]).unwrap();
+    assert!(
+        registry.read(hash).unwrap().is_some(),
+        "a content of the size of the limit should be written"
+    );
+}
+
+fn garbage_collection_checks(registry: &impl Registry) {
+    let used = registry.write(&b"used"[..]).unwrap();
+    let unused = registry.write(&b"unused"[..]).unwrap();
+    let mut contents = registry.contents().unwrap();
+    contents.sort_unstable();
+    let mut expected = vec![used, unused];
+    expected.sort_unstable();
+    assert_eq!(
+        contents, expected,
+        "all the written contents should be listed"
+    );
+
+    // Recently written contents are kept
+    let used_contents = HashSet::from([used]);
+    assert_eq!(
+        registry
+            .collect_garbage(&used_contents, Duration::from_secs(3600), false)
+            .unwrap(),
+        vec![],
+        "contents written during the grace period should be kept"
+    );
+
+    // A dry run does not remove anything
+    assert_eq!(
+        registry
+            .collect_garbage(&used_contents, Duration::ZERO, true)
+            .unwrap(),
+        vec![unused],
+        "a dry run should return the unused contents"
+    );
+    assert!(
+        registry.read(unused).unwrap().is_some(),
+        "a dry run should not remove anything"
+    );
+
+    // A real run removes the unused contents only
+    assert_eq!(
+        registry
+            .collect_garbage(&used_contents, Duration::ZERO, false)
+            .unwrap(),
+        vec![unused],
+        "the removed contents should be returned"
+    );
+    assert!(
+        registry.read(unused).unwrap().is_none(),
+        "the unused content should be removed"
+    );
+    assert!(
+        registry.read(used).unwrap().is_some(),
+        "the used content should be kept"
+    );
+    assert!(
+        !registry.remove(unused).unwrap(),
+        "removing a missing content should return false"
+    );
+}
+
+/// A [Registry] that writes a content again right before removing any
+/// content, like a write happening between the listing and the removal of
+/// the garbage.
+struct RewritingRegistry<'registry, R> {
+    /// The registry actually storing the contents.
+    registry: &'registry R,
+
+    /// The content written again before each removal.
+    rewritten: &'static [u8],
+}
+
+impl<R: Registry> Registry for RewritingRegistry<'_, R> {
+    type Error = R::Error;
+
+    fn read(&self, hash: ContentHash) -> Result<Option<impl Read>, Self::Error> {
+        self.registry.read(hash)
+    }
+
+    fn write(&self, content: impl Read) -> Result<ContentHash, Self::Error> {
+        self.registry.write(content)
+    }
+
+    fn size(&self, hash: ContentHash) -> Result<Option<u64>, Self::Error> {
+        self.registry.size(hash)
+    }
+
+    fn written_at(&self, hash: ContentHash) -> Result<Option<SystemTime>, Self::Error> {
+        self.registry.written_at(hash)
+    }
+
+    fn contents(&self) -> Result<Vec<ContentHash>, Self::Error> {
+        self.registry.contents()
+    }
+
+    fn remove(&self, hash: ContentHash) -> Result<bool, Self::Error> {
+        self.registry.write(self.rewritten)?;
+        self.registry.remove(hash)
+    }
+
+    fn remove_if_written_before(
+        &self,
+        hash: ContentHash,
+        time: SystemTime,
+    ) -> Result<bool, Self::Error> {
+        self.registry.write(self.rewritten)?;
+        self.registry.remove_if_written_before(hash, time)
+    }
+}
+
+fn concurrent_write_garbage_collection_checks(registry: &impl Registry) {
+    let rewritten = registry.write(&b"rewritten"[..]).unwrap();
+    let unused = registry.write(&b"unused"[..]).unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    // A content written again after being listed is not removed
+    let rewriting = RewritingRegistry {
+        registry,
+        rewritten: b"rewritten",
+    };
+    assert_eq!(
+        rewriting
+            .collect_garbage(&HashSet::new(), Duration::from_millis(200), false)
+            .unwrap(),
+        vec![unused],
+        "only the content that was not written again should be removed"
+    );
+    assert!(
+        registry.read(rewritten).unwrap().is_some(),
+        "the content written again should be kept"
+    );
+    assert!(
+        registry.read(unused).unwrap().is_none(),
+        "the unused content should be removed"
+    );
+}
+
+fn verified_write_checks(registry: &impl Registry<Error = io::Error>) {
+    let hash = registry.write(&b"hello"[..]).unwrap();
+
+    // A content with the expected hash is written
+    let world = MemoryRegistry::new().write(&b"world"[..]).unwrap();
+    registry.write_verified(&b"world"[..], world).unwrap();
+    assert!(
+        registry.exists(world).unwrap(),
+        "a content with the expected hash should be written"
+    );
+
+    // A content with another hash is never stored
+    let untrusted = MemoryRegistry::new().write(&b"untrusted"[..]).unwrap();
+    assert_eq!(
+        registry
+            .write_verified(&b"untrusted"[..], hash)
+            .map_err(|err| err.kind())
+            .unwrap_err(),
+        io::ErrorKind::InvalidData,
+        "a content with another hash should be rejected"
+    );
+    assert!(
+        !registry.exists(untrusted).unwrap(),
+        "a rejected content should not be stored"
+    );
+    let mut contents = registry.contents().unwrap();
+    contents.sort_unstable();
+    let mut expected = vec![hash, world];
+    expected.sort_unstable();
+    assert_eq!(
+        contents, expected,
+        "only the verified contents should be stored"
+    );
+}
+
+/// A [Registry] that only lists the given hashes, without any content.
+///
+/// This allows testing the behaviour of the provided methods of [Registry]
+/// with hashes that cannot be obtained by writing real contents.
+struct StubRegistry(Vec<ContentHash>);
+
+impl Registry for StubRegistry {
+    type Error = io::Error;
+
+    fn read(&self, _: ContentHash) -> Result<Option<impl Read>, Self::Error> {
+        Ok(None::<&[u8]>)
+    }
+
+    fn write(&self, _: impl Read) -> Result<ContentHash, Self::Error> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    fn size(&self, _: ContentHash) -> Result<Option<u64>, Self::Error> {
+        Ok(None)
+    }
+
+    fn written_at(&self, _: ContentHash) -> Result<Option<SystemTime>, Self::Error> {
+        Ok(None)
+    }
+
+    fn contents(&self) -> Result<Vec<ContentHash>, Self::Error> {
+        Ok(self.0.clone())
+    }
+
+    fn remove(&self, _: ContentHash) -> Result<bool, Self::Error> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+#[test]
+fn resolve_content() {
+    let registry = MemoryRegistry::new();
+    let hash = registry.write(&b"hello"[..]).unwrap();
+    registry.write(&b"world"[..]).unwrap();
+    let unknown = ContentHash::new([0; 32]);
+
+    // Full hashes and long enough prefixes are resolved
+    let full = hash.to_string();
+    assert_eq!(
+        registry.resolve_content(&full).unwrap(),
+        hash,
+        "a full hash should be resolved"
+    );
+    assert_eq!(
+        registry.resolve_content(&full[8..16]).unwrap(),
+        hash,
+        "a prefix without the content: part should be resolved"
+    );
+
+    // Invalid prefixes are rejected
+    assert!(
+        matches!(
+            registry.resolve_content(&full[..13]),
+            Err(ResolveContentError::TooShort(_))
+        ),
+        "a prefix of 5 characters should be too short"
+    );
+    assert!(
+        matches!(
+            registry.resolve_content(&unknown.to_string()),
+            Err(ResolveContentError::NotFound(_))
+        ),
+        "a hash that is not in the registry should not be found"
+    );
+}
+
+#[test]
+fn resolve_content_ambiguous() {
+    let mut first = [0; 32];
+    first[31] = 1;
+    let mut second = [0; 32];
+    second[31] = 2;
+    let (first, second) = (ContentHash::new(first), ContentHash::new(second));
+    let other = ContentHash::new([0xff; 32]);
+    let registry = StubRegistry(vec![second, other, first]);
+
+    // A prefix shared by several contents is ambiguous
+    match registry.resolve_content("content:AAAAAAAA") {
+        Err(ResolveContentError::Ambiguous(prefix, candidates)) => {
+            assert_eq!(prefix, "AAAAAAAA", "the prefix should be reported");
+            assert_eq!(
+                candidates,
+                vec![first, second],
+                "all the matching contents should be reported in order"
+            );
+        }
+        result => panic!("the prefix should be ambiguous, got {result:?}"),
+    }
+
+    // A prefix matching a single content is resolved
+    assert_eq!(
+        registry
+            .resolve_content("AAAAAAAB")
+            .unwrap_err()
+            .to_string(),
+        "no content matches the prefix \"AAAAAAAB\"",
+        "a prefix matching no content should not be found"
+    );
+    assert_eq!(
+        registry.resolve_content(&first.to_string()).unwrap(),
+        first,
+        "a prefix matching a single content should be resolved"
+    );
 }
 
 #[test]
@@ -49,9 +376,362 @@ fn memory_registry_checks() {
     registry_checks(MemoryRegistry::new());
 }
 
+#[test]
+fn memory_registry_max_content_size() {
+    max_content_size_checks(&MemoryRegistry::new().with_max_content_size(4));
+}
+
 #[test]
 fn persistent_registry_checks() {
     let temp_dir = TempDir::new().unwrap();
     registry_checks(PersistentRegistry::new(temp_dir.path()));
     temp_dir.close().unwrap();
 }
+
+#[test]
+fn memory_registry_garbage_collection() {
+    garbage_collection_checks(&MemoryRegistry::new());
+}
+
+#[test]
+fn persistent_registry_garbage_collection() {
+    let temp_dir = TempDir::new().unwrap();
+    let registry = PersistentRegistry::new(temp_dir.path());
+    assert!(
+        registry.contents().unwrap().is_empty(),
+        "a registry without a folder should be empty"
+    );
+    garbage_collection_checks(&registry);
+
+    // Files that are not contents are never listed
+    fs::write(temp_dir.path().join("temporary"), b"data").unwrap();
+    assert_eq!(
+        registry.contents().unwrap().len(),
+        1,
+        "only the contents should be listed"
+    );
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn memory_registry_concurrent_write_garbage_collection() {
+    concurrent_write_garbage_collection_checks(&MemoryRegistry::new());
+}
+
+#[test]
+fn persistent_registry_concurrent_write_garbage_collection() {
+    let temp_dir = TempDir::new().unwrap();
+    let registry = PersistentRegistry::new(temp_dir.path());
+    concurrent_write_garbage_collection_checks(&registry);
+    assert!(
+        temp_dir.path().read_dir().unwrap().all(|entry| entry
+            .unwrap()
+            .file_type()
+            .unwrap()
+            .is_dir()),
+        "no removed content should be left behind"
+    );
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn memory_registry_verified_write() {
+    verified_write_checks(&MemoryRegistry::new());
+}
+
+#[test]
+fn persistent_registry_verified_write() {
+    let temp_dir = TempDir::new().unwrap();
+    verified_write_checks(&PersistentRegistry::new(temp_dir.path()));
+    assert!(
+        temp_dir.path().read_dir().unwrap().all(|entry| entry
+            .unwrap()
+            .file_type()
+            .unwrap()
+            .is_dir()),
+        "no temporary file should be left behind"
+    );
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_registry_verification() {
+    let temp_dir = TempDir::new().unwrap();
+    let registry = PersistentRegistry::new(temp_dir.path());
+    let hash = registry.write(&b"hello"[..]).unwrap();
+    let other_hash = registry.write(&b"world"[..]).unwrap();
+
+    // Writing with the expected hash checks the content
+    registry.write_verified(&b"hello"[..], hash).unwrap();
+    assert_eq!(
+        registry
+            .write_verified(&b"hello"[..], other_hash)
+            .map_err(|err| err.kind())
+            .unwrap_err(),
+        io::ErrorKind::InvalidData,
+        "a content with another hash should be rejected"
+    );
+
+    // Stored contents are checked against their hash
+    assert_eq!(
+        registry.verify(hash).unwrap(),
+        Some(true),
+        "an untouched content should be valid"
+    );
+    assert_eq!(
+        registry.verify(ContentHash::new([0; 32])).unwrap(),
+        None,
+        "a missing content cannot be verified"
+    );
+    let encoded_hash = hash.to_string().replace("content:", "");
+    let (subfolder, file) = encoded_hash.split_at(2);
+    fs::write(temp_dir.path().join(subfolder).join(file), b"corrupted").unwrap();
+    assert_eq!(
+        registry.verify(hash).unwrap(),
+        Some(false),
+        "a modified content should be invalid"
+    );
+    assert_eq!(
+        registry.verify_all().unwrap(),
+        vec![hash],
+        "only the corrupted contents should be returned"
+    );
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_registry_backup() {
+    let temp_dir = TempDir::new().unwrap();
+    let backup_dir = TempDir::new().unwrap();
+    let registry = PersistentRegistry::new(temp_dir.path());
+    let hash = registry.write(&b"hello"[..]).unwrap();
+    registry.backup(backup_dir.path()).unwrap();
+    registry.backup(backup_dir.path()).unwrap();
+
+    let backup = PersistentRegistry::new(backup_dir.path());
+    assert_eq!(
+        backup.contents().unwrap(),
+        vec![hash],
+        "the backup should contain all the contents"
+    );
+    assert_eq!(
+        backup.verify(hash).unwrap(),
+        Some(true),
+        "the backed up contents should not change"
+    );
+
+    backup_dir.close().unwrap();
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_registry_max_content_size() {
+    let temp_dir = TempDir::new().unwrap();
+    let registry = PersistentRegistry::new(temp_dir.path()).with_max_content_size(4);
+    assert_eq!(
+        registry
+            .write(&b"hello"[..])
+            .map_err(|err| err.kind())
+            .unwrap_err(),
+        io::ErrorKind::FileTooLarge,
+        "a content bigger than the limit should be rejected"
+    );
+    assert_eq!(
+        fs::read_dir(temp_dir.path()).unwrap().count(),
+        0,
+        "the temporary file should be removed"
+    );
+    max_content_size_checks(&registry);
+    temp_dir.close().unwrap();
+}
+
+async fn async_registry_checks(registry: impl AsyncRegistry) {
+    for _ in 0..1024 {
+        async_read_a_non_written_value(&registry).await;
+    }
+
+    async_read_a_written_value(
+        &registry,
+        b"hello",
+        "content:LPJNul-wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ",
+    )
+    .await;
+
+    async_read_a_written_value(
+        &registry,
+        b"world",
+        "content:SG6kYiTRu0-2gPNPfJrZao8k7Ii-c-qOWmxlJg6cuKc",
+    )
+    .await;
+
+    async_read_a_written_value(
+        &registry,
+        b"",
+        "content:47DEQpj8HBSa-_TImW-5JCeuQeRkm5NMpJWZG3hSuFU",
+    )
+    .await;
+}
+
+async fn async_read_a_non_written_value(registry: &impl AsyncRegistry) {
+    let random_hash = ContentHash::new(rand::random());
+    let read_content = registry.read(random_hash).await.unwrap();
+    assert!(read_content.is_none(), "the content should not be found");
+    assert_eq!(
+        registry.size(random_hash).await.unwrap(),
+        None,
+        "the content should have no size"
+    );
+}
+
+async fn async_read_a_written_value(
+    registry: &impl AsyncRegistry,
+    value: &[u8],
+    expected_hash: &str,
+) {
+    let hash = registry.write(value).await.unwrap();
+    assert_eq!(
+        hash.to_string(),
+        expected_hash,
+        "the hash should be a sha-256 hash of the content"
+    );
+    let mut read_content = registry.read(hash).await.unwrap().unwrap();
+    let mut buffer = Vec::new();
+    read_content.read_to_end(&mut buffer).await.unwrap();
+    assert_eq!(buffer, value, "the content should not change");
+    assert_eq!(
+        registry.size(hash).await.unwrap(),
+        Some(value.len() as u64),
+        "the size should be the length of the content"
+    );
+}
+
+#[tokio::test]
+async fn async_persistent_registry_checks() {
+    let temp_dir = TempDir::new().unwrap();
+    async_registry_checks(AsyncPersistentRegistry::new(temp_dir.path(), WRITE_SLOTS)).await;
+    temp_dir.close().unwrap();
+}
+
+#[tokio::test]
+async fn async_persistent_registry_shares_sync_contents() {
+    let temp_dir = TempDir::new().unwrap();
+    let async_registry = AsyncPersistentRegistry::new(temp_dir.path(), WRITE_SLOTS);
+    let registry = PersistentRegistry::new(temp_dir.path());
+
+    let hash = async_registry.write(&b"hello"[..]).await.unwrap();
+    let mut buffer = Vec::new();
+    registry
+        .read(hash)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut buffer)
+        .unwrap();
+    assert_eq!(
+        buffer, b"hello",
+        "both registries should use the same layout"
+    );
+
+    temp_dir.close().unwrap();
+}
+
+#[tokio::test]
+async fn async_persistent_registry_max_content_size() {
+    let temp_dir = TempDir::new().unwrap();
+    let registry =
+        AsyncPersistentRegistry::new(temp_dir.path(), WRITE_SLOTS).with_max_content_size(4);
+    assert_eq!(
+        registry
+            .write(&b"hello"[..])
+            .await
+            .map_err(|err| err.kind())
+            .unwrap_err(),
+        io::ErrorKind::FileTooLarge,
+        "a content bigger than the limit should be rejected"
+    );
+    assert_eq!(
+        fs::read_dir(temp_dir.path()).unwrap().count(),
+        0,
+        "the temporary file should be removed"
+    );
+    let hash = registry.write(&b"hell"[..]).await.unwrap();
+    assert!(
+        registry.read(hash).await.unwrap().is_some(),
+        "a content of the size of the limit should be written"
+    );
+    temp_dir.close().unwrap();
+}
+
+/// A content that stays pending for a while and counts how many contents are
+/// being read at the same time.
+struct SlowContent {
+    /// The byte making the content unique.
+    byte: u8,
+
+    /// The delay before the content can be read.
+    delay: Pin<Box<Sleep>>,
+
+    /// The state of the read, `None` before it starts and `Some(true)` once
+    /// it is finished.
+    finished: Option<bool>,
+
+    /// The number of contents being read.
+    active: Arc<AtomicUsize>,
+
+    /// The maximum number of contents read at the same time.
+    max_active: Arc<AtomicUsize>,
+}
+
+impl AsyncRead for SlowContent {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.finished.is_none() {
+            self.finished = Some(false);
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_active.fetch_max(active, Ordering::SeqCst);
+        }
+        if self.delay.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        if self.finished == Some(false) {
+            self.finished = Some(true);
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            buf.put_slice(&[self.byte]);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn async_persistent_registry_write_slots() {
+    let temp_dir = TempDir::new().unwrap();
+    let write_slots = NonZeroUsize::new(2).unwrap();
+    let registry = Arc::new(AsyncPersistentRegistry::new(temp_dir.path(), write_slots));
+    let active = Arc::new(AtomicUsize::new(0));
+    let max_active = Arc::new(AtomicUsize::new(0));
+
+    let mut writes = JoinSet::new();
+    for byte in 0..8 {
+        let registry = Arc::clone(&registry);
+        let content = SlowContent {
+            byte,
+            delay: Box::pin(tokio::time::sleep(Duration::from_millis(50))),
+            finished: None,
+            active: Arc::clone(&active),
+            max_active: Arc::clone(&max_active),
+        };
+        writes.spawn(async move { registry.write(content).await });
+    }
+    while let Some(result) = writes.join_next().await {
+        result.unwrap().unwrap();
+    }
+
+    assert_eq!(
+        max_active.load(Ordering::SeqCst),
+        write_slots.get(),
+        "at most write_slots contents should be written at the same time"
+    );
+    temp_dir.close().unwrap();
+}
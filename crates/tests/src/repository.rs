@@ -0,0 +1,1165 @@
+//! Tests on [Repository]
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use std::{io, thread};
+
+use rand::Rng;
+use solipr_core::change::{Change, ChangeContent, ChangeHash, FileId, LineId, SingleId};
+use solipr_core::registry::{ContentHash, Registry};
+use solipr_core::repository::{Repository, RepositoryId, RepositoryManager, ResolveChangeError};
+use solipr_memory::registry::MemoryRegistry;
+use solipr_memory::repository::MemoryRepositoryManager;
+use solipr_persistent::events::RepositoryEvent;
+use solipr_persistent::integrity::IntegrityIssue;
+use solipr_persistent::repository::{BACKUP_BATCH_SIZE, PersistentRepositoryManager};
+use solipr_stack::StackVec;
+use tempfile::TempDir;
+
+fn random_file_id() -> FileId {
+    format!("{:032x}", rand::random::<u128>()).parse().unwrap()
+}
+
+fn random_line_id() -> LineId {
+    format!("{:032x}", rand::random::<u128>()).parse().unwrap()
+}
+
+fn random_line_existence() -> Change {
+    let file_id = random_file_id();
+    let line_id = random_line_id();
+    Change {
+        replace: StackVec::new(),
+        content: ChangeContent::LineExistence {
+            file_id,
+            line_id,
+            existence: true,
+        },
+    }
+}
+
+#[test]
+fn persistent_events_journal() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let repository_id = RepositoryId::create_new();
+    let change = random_line_existence();
+
+    // Applying a change records a single event, even if applied twice, after
+    // the creation of the repository
+    let mut repository = manager.open_write(repository_id).unwrap();
+    let change_hash = repository.apply(change).unwrap();
+    repository.apply(change).unwrap();
+    assert!(
+        manager.events(0, usize::MAX).unwrap().is_empty(),
+        "uncommitted events should not be visible"
+    );
+    repository.commit().unwrap();
+    assert_eq!(
+        manager.events(0, usize::MAX).unwrap(),
+        vec![
+            (1, RepositoryEvent::RepositoryCreated(repository_id)),
+            (
+                2,
+                RepositoryEvent::ChangeApplied(repository_id, change_hash)
+            ),
+        ],
+        "the creation and the applied change should be recorded once"
+    );
+
+    // Unapplying a change records an event, but only if it was applied
+    let mut repository = manager.open_write(repository_id).unwrap();
+    repository.unapply(change_hash).unwrap();
+    repository.unapply(change_hash).unwrap();
+    repository.commit().unwrap();
+    assert_eq!(
+        manager.events(2, usize::MAX).unwrap(),
+        vec![(
+            3,
+            RepositoryEvent::ChangeUnapplied(repository_id, change_hash)
+        )],
+        "only the events after the given sequence number should be returned"
+    );
+
+    // Applying a change to an empty repository creates it again
+    let mut repository = manager.open_write(repository_id).unwrap();
+    repository.apply(change).unwrap();
+    repository.commit().unwrap();
+    assert_eq!(
+        manager.events(3, usize::MAX).unwrap(),
+        vec![
+            (4, RepositoryEvent::RepositoryCreated(repository_id)),
+            (
+                5,
+                RepositoryEvent::ChangeApplied(repository_id, change_hash)
+            ),
+        ],
+        "a repository without changes should be created again"
+    );
+
+    // The number of returned events is limited
+    assert_eq!(
+        manager.events(0, usize::MAX).unwrap().len(),
+        5,
+        "the journal should contain all the events"
+    );
+    assert_eq!(
+        manager.events(1, 2).unwrap(),
+        vec![
+            (
+                2,
+                RepositoryEvent::ChangeApplied(repository_id, change_hash)
+            ),
+            (
+                3,
+                RepositoryEvent::ChangeUnapplied(repository_id, change_hash)
+            ),
+        ],
+        "at most the given number of events should be returned"
+    );
+    assert!(
+        manager.events(0, 0).unwrap().is_empty(),
+        "no events should be returned with a limit of 0"
+    );
+
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_resolve_change() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let mut repository = manager.open_write(RepositoryId::create_new()).unwrap();
+    let change_hash = repository.apply(random_line_existence()).unwrap();
+    repository.apply(random_line_existence()).unwrap();
+    let unknown_hash = random_line_existence().calculate_hash();
+
+    // Full hashes and long enough prefixes are resolved
+    let full = change_hash.to_string();
+    assert_eq!(
+        repository.resolve_change(&full).unwrap(),
+        change_hash,
+        "a full hash should be resolved"
+    );
+    assert_eq!(
+        repository.resolve_change(&full[7..15]).unwrap(),
+        change_hash,
+        "a prefix without the change: part should be resolved"
+    );
+
+    // Invalid prefixes are rejected
+    assert!(
+        matches!(
+            repository.resolve_change(&full[..12]),
+            Err(ResolveChangeError::TooShort(_))
+        ),
+        "a prefix of 5 characters should be too short"
+    );
+    assert!(
+        matches!(
+            repository.resolve_change(&unknown_hash.to_string()),
+            Err(ResolveChangeError::NotFound(_))
+        ),
+        "a hash that is not in the repository should not be found"
+    );
+
+    drop(repository);
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+/// A [Repository] that only contains the given changes, whatever their hash.
+///
+/// This allows testing the behaviour of the provided methods of [Repository]
+/// with hashes that cannot be obtained by applying real changes.
+struct StubRepository(Vec<(ChangeHash, Change)>);
+
+/// Returns the error of the operations that are not supported by
+/// [`StubRepository`].
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unsupported stub repository operation",
+    )
+}
+
+impl Repository<'_> for StubRepository {
+    type Error = io::Error;
+
+    fn changes(&self) -> impl Iterator<Item = Result<(ChangeHash, Change), Self::Error>> {
+        self.0.iter().copied().map(Ok)
+    }
+
+    fn change(&self, change_hash: ChangeHash) -> Result<Option<Change>, Self::Error> {
+        Ok(self
+            .0
+            .iter()
+            .find(|&&(hash, _)| hash == change_hash)
+            .map(|&(_, change)| change))
+    }
+
+    fn heads(&self, _: SingleId) -> Result<HashSet<ChangeHash>, Self::Error> {
+        Err(unsupported())
+    }
+
+    fn existing_lines(&self, _: FileId) -> Result<HashSet<LineId>, Self::Error> {
+        Err(unsupported())
+    }
+
+    fn files(&self) -> Result<HashSet<FileId>, Self::Error> {
+        Err(unsupported())
+    }
+
+    fn apply(&mut self, _: Change) -> Result<ChangeHash, Self::Error> {
+        Err(unsupported())
+    }
+
+    fn unapply(&mut self, _: ChangeHash) -> Result<(), Self::Error> {
+        Err(unsupported())
+    }
+
+    fn commit(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn resolve_change_ambiguous() {
+    let first: ChangeHash = format!("AAAAAAAA{}A", "B".repeat(34)).parse().unwrap();
+    let second: ChangeHash = format!("AAAAAAAA{}A", "C".repeat(34)).parse().unwrap();
+    let other: ChangeHash = format!("BBBBBBBB{}A", "C".repeat(34)).parse().unwrap();
+    let repository = StubRepository(vec![
+        (second, random_line_existence()),
+        (other, random_line_existence()),
+        (first, random_line_existence()),
+    ]);
+
+    // A prefix shared by several changes is ambiguous
+    match repository.resolve_change("change:AAAAAAAA") {
+        Err(ResolveChangeError::Ambiguous(prefix, candidates)) => {
+            assert_eq!(prefix, "AAAAAAAA", "the prefix should be reported");
+            assert_eq!(
+                candidates,
+                vec![first, second],
+                "all the matching changes should be reported in order"
+            );
+        }
+        result => panic!("the prefix should be ambiguous, got {result:?}"),
+    }
+
+    // A longer prefix resolves a single change
+    assert_eq!(
+        repository.resolve_change("AAAAAAAAB").unwrap(),
+        first,
+        "a prefix matching a single change should be resolved"
+    );
+}
+
+/// Checks that the history of a repository contains all its changes and that
+/// every change comes after the applied changes it replaces.
+fn check_history<'manager>(repository: &impl Repository<'manager>) {
+    let history = repository.history().unwrap();
+    let positions = history
+        .iter()
+        .enumerate()
+        .map(|(position, &(change_hash, _))| (change_hash, position))
+        .collect::<HashMap<ChangeHash, usize>>();
+    assert_eq!(
+        positions.len(),
+        repository.changes().count(),
+        "the history should contain every applied change once"
+    );
+    for (position, (_, change)) in history.iter().enumerate() {
+        for replaced in &change.replace {
+            if let Some(&replaced_position) = positions.get(&replaced) {
+                assert!(
+                    replaced_position < position,
+                    "a change should come after the changes it replaces"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn persistent_history_order() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let mut repository = manager.open_write(RepositoryId::create_new()).unwrap();
+
+    // Build a chain of changes and apply it backwards
+    let first = random_line_existence();
+    let mut chain = vec![first];
+    for _ in 0..3 {
+        let previous = *chain.last().unwrap();
+        let mut replace = StackVec::new();
+        replace.push(previous.calculate_hash());
+        chain.push(Change {
+            replace,
+            content: previous.content,
+        });
+    }
+    let unrelated = random_line_existence();
+    repository.apply(unrelated).unwrap();
+    for &change in chain.iter().rev() {
+        repository.apply(change).unwrap();
+    }
+
+    let history = repository.history().unwrap();
+    let chain_order = history
+        .iter()
+        .filter(|&&(change_hash, _)| change_hash != unrelated.calculate_hash())
+        .map(|&(_, change)| change)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        chain_order, chain,
+        "the changes should come after the changes they replace"
+    );
+    assert_eq!(
+        repository.history().unwrap(),
+        history,
+        "the history order should be deterministic"
+    );
+    check_history(&repository);
+
+    drop(repository);
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_apply_bundle() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let repository_id = RepositoryId::create_new();
+
+    // A bundle is applied in topological order
+    let first = random_line_existence();
+    let mut replace = StackVec::new();
+    replace.push(first.calculate_hash());
+    let second = Change {
+        replace,
+        content: first.content,
+    };
+    let mut repository = manager.open_write(repository_id).unwrap();
+    assert_eq!(
+        repository.apply_bundle(&[second, first, second]).unwrap(),
+        vec![first.calculate_hash(), second.calculate_hash()],
+        "the bundle should be applied once per change, in topological order"
+    );
+    assert_eq!(
+        repository.heads(first.single_id()).unwrap(),
+        HashSet::from([second.calculate_hash()]),
+        "the heads should take the whole bundle into account"
+    );
+    repository.commit().unwrap();
+
+    // A failing bundle is discarded if the repository is not committed
+    let valid = random_line_existence();
+    let mut replace = StackVec::new();
+    replace.push(valid.calculate_hash());
+    replace.push(valid.calculate_hash());
+    let invalid = Change {
+        replace,
+        content: valid.content,
+    };
+    let mut repository = manager.open_write(repository_id).unwrap();
+    assert!(
+        repository.apply_bundle(&[valid, invalid]).is_err(),
+        "a bundle containing an invalid change should fail"
+    );
+    drop(repository);
+    let repository = manager.open_read(repository_id).unwrap();
+    assert_eq!(
+        repository.change(valid.calculate_hash()).unwrap(),
+        None,
+        "no change of a failed bundle should be applied"
+    );
+    assert_eq!(
+        repository.changes().count(),
+        2,
+        "the first bundle should still be applied"
+    );
+
+    drop(repository);
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_used_contents() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let mut repository = manager.open_write(RepositoryId::create_new()).unwrap();
+    let content = ContentHash::new([1; 32]);
+    repository.apply(random_line_existence()).unwrap();
+    let change_hash = repository
+        .apply(Change {
+            replace: StackVec::new(),
+            content: ChangeContent::LineContent {
+                file_id: random_file_id(),
+                line_id: random_line_id(),
+                content,
+            },
+        })
+        .unwrap();
+    assert_eq!(
+        repository.used_contents().unwrap(),
+        HashSet::from([content]),
+        "the contents of the applied changes should be used"
+    );
+
+    repository.unapply(change_hash).unwrap();
+    assert!(
+        repository.used_contents().unwrap().is_empty(),
+        "the contents of unapplied changes should not be used"
+    );
+
+    drop(repository);
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_backup() {
+    let temp_dir = TempDir::new().unwrap();
+    let backup_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let repository_id = RepositoryId::create_new();
+    let backed_up = random_line_existence();
+    let not_backed_up = random_line_existence();
+
+    let mut repository = manager.open_write(repository_id).unwrap();
+    repository.apply(backed_up).unwrap();
+    repository.commit().unwrap();
+    manager.backup(backup_dir.path()).unwrap();
+    let mut repository = manager.open_write(repository_id).unwrap();
+    repository.apply(not_backed_up).unwrap();
+    repository.commit().unwrap();
+    assert!(
+        manager.backup(backup_dir.path()).is_err(),
+        "a backup should not overwrite a non-empty folder"
+    );
+    drop(manager);
+
+    // The backup can be opened as a database
+    let backup = PersistentRepositoryManager::create(backup_dir.path()).unwrap();
+    let repository = backup.open_read(repository_id).unwrap();
+    assert_eq!(
+        repository.change(backed_up.calculate_hash()).unwrap(),
+        Some(backed_up),
+        "the backup should contain the changes committed before it"
+    );
+    assert_eq!(
+        repository.change(not_backed_up.calculate_hash()).unwrap(),
+        None,
+        "the backup should not contain the changes committed after it"
+    );
+    assert_eq!(
+        repository.heads(backed_up.single_id()).unwrap(),
+        HashSet::from([backed_up.calculate_hash()]),
+        "the backup should contain the indexes"
+    );
+    assert_eq!(
+        backup.events(0, usize::MAX).unwrap().len(),
+        2,
+        "the backup should contain the events journal"
+    );
+    drop(repository);
+    drop(backup);
+
+    backup_dir.close().unwrap();
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_backup_into_new_folder() {
+    let temp_dir = TempDir::new().unwrap();
+    let parent_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let repository_id = RepositoryId::create_new();
+    let mut repository = manager.open_write(repository_id).unwrap();
+    let change_hash = repository.apply(random_line_existence()).unwrap();
+    repository.commit().unwrap();
+
+    // The backup is moved into place without leaving its temporary folder
+    let backup_path = parent_dir.path().join("backup");
+    manager.backup(&backup_path).unwrap();
+    assert_eq!(
+        parent_dir
+            .path()
+            .read_dir()
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>(),
+        ["backup"],
+        "only the backup folder should be written"
+    );
+    let backup = PersistentRepositoryManager::create(&backup_path).unwrap();
+    assert!(
+        backup
+            .open_read(repository_id)
+            .unwrap()
+            .change(change_hash)
+            .unwrap()
+            .is_some(),
+        "the backup should contain the changes"
+    );
+    drop(backup);
+
+    // A failed backup does not leave anything behind
+    let file_path = parent_dir.path().join("file");
+    std::fs::write(&file_path, b"not a folder").unwrap();
+    assert!(
+        manager.backup(file_path.join("backup")).is_err(),
+        "a backup inside a file should fail"
+    );
+    assert_eq!(
+        parent_dir.path().read_dir().unwrap().count(),
+        2,
+        "a failed backup should not leave a folder behind"
+    );
+
+    drop(manager);
+    parent_dir.close().unwrap();
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_backup_in_batches() {
+    let temp_dir = TempDir::new().unwrap();
+    let backup_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let repository_id = RepositoryId::create_new();
+
+    // Write more entries than a single backup batch
+    let mut repository = manager.open_write(repository_id).unwrap();
+    for _ in 0..BACKUP_BATCH_SIZE {
+        repository.apply(random_line_existence()).unwrap();
+    }
+    repository.commit().unwrap();
+    manager.backup(backup_dir.path()).unwrap();
+
+    // All the batches are in the backup
+    let backup = PersistentRepositoryManager::create(backup_dir.path()).unwrap();
+    assert_eq!(
+        backup.open_read(repository_id).unwrap().history().unwrap(),
+        manager.open_read(repository_id).unwrap().history().unwrap(),
+        "the backup should contain all the changes"
+    );
+    assert_eq!(
+        backup.events(0, usize::MAX).unwrap().len(),
+        BACKUP_BATCH_SIZE + 1,
+        "the backup should contain all the events"
+    );
+    drop(backup);
+    drop(manager);
+
+    backup_dir.close().unwrap();
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_check_integrity() {
+    let temp_dir = TempDir::new().unwrap();
+    let registry = MemoryRegistry::new();
+    let repository_id = RepositoryId::create_new();
+    let file_id = random_file_id();
+    let [existence, parent, child] = insert_line(file_id);
+    let ChangeContent::LineExistence { line_id, .. } = existence.content else {
+        unreachable!("insert_line starts with the existence of the line");
+    };
+    let missing_content = ContentHash::new([1; 32]);
+
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let mut repository = manager.open_write(repository_id).unwrap();
+    for change in [existence, parent, child] {
+        repository.apply(change).unwrap();
+    }
+    repository
+        .apply(Change {
+            replace: StackVec::new(),
+            content: ChangeContent::LineContent {
+                file_id,
+                line_id,
+                content: missing_content,
+            },
+        })
+        .unwrap();
+    repository.commit().unwrap();
+    assert_eq!(
+        manager
+            .check_integrity(repository_id, &registry, false)
+            .unwrap(),
+        vec![IntegrityIssue::MissingContent(missing_content)],
+        "a healthy repository should only miss its unwritten contents"
+    );
+    drop(manager);
+
+    // Lose the heads of the line existence and the line itself
+    let keyspace = fjall::Config::new(temp_dir.path())
+        .open_transactional()
+        .unwrap();
+    let heads = keyspace
+        .open_partition("heads", fjall::PartitionCreateOptions::default())
+        .unwrap();
+    let lines = keyspace
+        .open_partition("lines", fjall::PartitionCreateOptions::default())
+        .unwrap();
+    let mut tx = keyspace.write_tx();
+    tx.remove(
+        &heads,
+        borsh::to_vec(&(repository_id, existence.single_id())).unwrap(),
+    );
+    tx.remove(
+        &lines,
+        borsh::to_vec(&(repository_id, file_id, line_id)).unwrap(),
+    );
+    tx.commit().unwrap();
+    drop(heads);
+    drop(lines);
+    drop(keyspace);
+
+    // The issues are reported, and only fixed when repairing
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let expected = vec![
+        IntegrityIssue::Heads(existence.single_id()),
+        IntegrityIssue::Line(file_id, line_id),
+        IntegrityIssue::MissingContent(missing_content),
+    ];
+    assert_eq!(
+        manager
+            .check_integrity(repository_id, &registry, false)
+            .unwrap(),
+        expected,
+        "the lost heads and line should be reported"
+    );
+    assert_eq!(
+        manager
+            .check_integrity(repository_id, &registry, false)
+            .unwrap(),
+        expected,
+        "a check without repair should not change anything"
+    );
+    assert_eq!(
+        manager
+            .check_integrity(repository_id, &registry, true)
+            .unwrap(),
+        expected,
+        "the repair should report the issues it fixes"
+    );
+    assert_eq!(
+        manager
+            .check_integrity(repository_id, &registry, false)
+            .unwrap(),
+        vec![IntegrityIssue::MissingContent(missing_content)],
+        "the repairable issues should be repaired"
+    );
+    let repository = manager.open_read(repository_id).unwrap();
+    assert_eq!(
+        repository.heads(existence.single_id()).unwrap(),
+        HashSet::from([existence.calculate_hash()]),
+        "the lost heads should be restored"
+    );
+    assert_eq!(
+        repository.existing_lines(file_id).unwrap(),
+        HashSet::from([line_id]),
+        "the repaired line should exist again"
+    );
+    drop(repository);
+
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_check_integrity_undecodable() {
+    let temp_dir = TempDir::new().unwrap();
+    let registry = MemoryRegistry::new();
+    let repository_id = RepositoryId::create_new();
+    let [existence, parent, child] = insert_line(random_file_id());
+
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let mut repository = manager.open_write(repository_id).unwrap();
+    for change in [existence, parent, child] {
+        repository.apply(change).unwrap();
+    }
+    repository.commit().unwrap();
+    drop(manager);
+
+    // Corrupt a change and the heads of the line existence
+    let keyspace = fjall::Config::new(temp_dir.path())
+        .open_transactional()
+        .unwrap();
+    let changes = keyspace
+        .open_partition("changes", fjall::PartitionCreateOptions::default())
+        .unwrap();
+    let heads = keyspace
+        .open_partition("heads", fjall::PartitionCreateOptions::default())
+        .unwrap();
+    let change_key = borsh::to_vec(&(repository_id, [7_u8; 32])).unwrap();
+    let heads_key = borsh::to_vec(&(repository_id, existence.single_id())).unwrap();
+    let mut tx = keyspace.write_tx();
+    tx.insert(&changes, &change_key, b"garbage");
+    tx.insert(&heads, &heads_key, b"garbage");
+    tx.commit().unwrap();
+    drop(changes);
+    drop(heads);
+    drop(keyspace);
+
+    // The undecodable entries are reported without blocking the writers
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let expected = vec![
+        IntegrityIssue::Heads(existence.single_id()),
+        IntegrityIssue::Undecodable("changes", change_key),
+        IntegrityIssue::Undecodable("heads", heads_key),
+    ];
+    let writer = manager.open_write(RepositoryId::create_new()).unwrap();
+    assert_eq!(
+        manager
+            .check_integrity(repository_id, &registry, false)
+            .unwrap(),
+        expected,
+        "the undecodable entries should be reported"
+    );
+    drop(writer);
+
+    // Repairing removes them and rebuilds the indexes
+    assert_eq!(
+        manager
+            .check_integrity(repository_id, &registry, true)
+            .unwrap(),
+        expected,
+        "the repair should report the issues it fixes"
+    );
+    assert_eq!(
+        manager
+            .check_integrity(repository_id, &registry, false)
+            .unwrap(),
+        vec![],
+        "the undecodable entries should be repaired"
+    );
+    let repository = manager.open_read(repository_id).unwrap();
+    assert_eq!(
+        repository.heads(existence.single_id()).unwrap(),
+        HashSet::from([existence.calculate_hash()]),
+        "the undecodable heads should be rebuilt"
+    );
+    drop(repository);
+
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn persistent_fork() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    let source_id = RepositoryId::create_new();
+    let change = random_line_existence();
+
+    let mut source = manager.open_write(source_id).unwrap();
+    let change_hash = source.apply(change).unwrap();
+    source.commit().unwrap();
+
+    // The fork contains the changes of the source
+    let fork_id = manager.fork(source_id).unwrap();
+    assert_ne!(fork_id, source_id, "the fork should be a new repository");
+    assert_eq!(
+        manager.fork_origin(fork_id).unwrap(),
+        Some(source_id),
+        "the origin of the fork should be recorded"
+    );
+    assert_eq!(
+        manager.fork_origin(source_id).unwrap(),
+        None,
+        "the source is not a fork"
+    );
+    let fork = manager.open_read(fork_id).unwrap();
+    assert_eq!(
+        fork.change(change_hash).unwrap(),
+        Some(change),
+        "the fork should contain the changes of the source"
+    );
+    assert_eq!(
+        fork.heads(change.single_id()).unwrap(),
+        HashSet::from([change_hash]),
+        "the fork should have the same heads as the source"
+    );
+    drop(fork);
+    assert!(
+        manager
+            .events(0, usize::MAX)
+            .unwrap()
+            .contains(&(3, RepositoryEvent::RepositoryCreated(fork_id))),
+        "the creation of the fork should be recorded"
+    );
+
+    // Modifying the fork does not modify the source
+    let mut fork = manager.open_write(fork_id).unwrap();
+    fork.unapply(change_hash).unwrap();
+    fork.commit().unwrap();
+    let source = manager.open_read(source_id).unwrap();
+    assert_eq!(
+        source.change(change_hash).unwrap(),
+        Some(change),
+        "the source should not be modified by the fork"
+    );
+    drop(source);
+
+    // Repositories without any change cannot be forked
+    let events = manager.events(0, usize::MAX).unwrap();
+    assert!(
+        manager.fork(RepositoryId::create_new()).is_err(),
+        "an unknown repository should not be forked"
+    );
+    assert!(
+        manager.fork(fork_id).is_err(),
+        "a repository without any change should not be forked"
+    );
+    assert_eq!(
+        manager.events(0, usize::MAX).unwrap(),
+        events,
+        "a failed fork should not create a repository"
+    );
+
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+/// Returns the changes that insert a new line at the beginning of a file.
+fn insert_line(file_id: FileId) -> [Change; 3] {
+    let line_id = random_line_id();
+    [
+        ChangeContent::LineExistence {
+            file_id,
+            line_id,
+            existence: true,
+        },
+        ChangeContent::LineParent {
+            file_id,
+            line_id,
+            parent: LineId::FIRST,
+        },
+        ChangeContent::LineChild {
+            file_id,
+            line_id,
+            child: LineId::LAST,
+        },
+    ]
+    .map(|content| Change {
+        replace: StackVec::new(),
+        content,
+    })
+}
+
+/// Checks that a repository never contains a partially applied
+/// [`insert_line`] and returns the number of lines in the file.
+fn check_consistency<'manager>(repository: &impl Repository<'manager>, file_id: FileId) -> usize {
+    let changes = repository.changes().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(
+        changes.len() % 3,
+        0,
+        "a snapshot should never contain a partial transaction"
+    );
+    let lines = repository.existing_lines(file_id).unwrap();
+    assert_eq!(
+        lines.len() * 3,
+        changes.len(),
+        "the lines and changes partitions should be consistent"
+    );
+    for line_id in &lines {
+        for single_id in [
+            SingleId::LineExistence(file_id, *line_id),
+            SingleId::LineParent(file_id, *line_id),
+            SingleId::LineChild(file_id, *line_id),
+        ] {
+            let heads = repository.heads(single_id).unwrap();
+            assert_eq!(
+                heads.len(),
+                1,
+                "the heads and lines partitions should be consistent"
+            );
+            for head in heads {
+                assert!(
+                    repository.change(head).unwrap().is_some(),
+                    "the heads and changes partitions should be consistent"
+                );
+            }
+        }
+    }
+    lines.len()
+}
+
+fn snapshot_isolation(manager: &(impl RepositoryManager + Sync)) {
+    const TRANSACTIONS: usize = 64;
+
+    let repository_id = RepositoryId::create_new();
+    let file_id = random_file_id();
+
+    // A snapshot opened before any write never sees them
+    let snapshot = manager.open_read(repository_id).unwrap();
+
+    thread::scope(|scope| {
+        let writer = scope.spawn(|| {
+            for _ in 0..TRANSACTIONS {
+                let mut repository = manager.open_write(repository_id).unwrap();
+                for change in insert_line(file_id) {
+                    repository.apply(change).unwrap();
+                }
+                repository.commit().unwrap();
+            }
+        });
+
+        // Snapshots opened during the writes only see whole transactions
+        let mut last_line_count = 0;
+        while !writer.is_finished() {
+            let repository = manager.open_read(repository_id).unwrap();
+            let line_count = check_consistency(&repository, file_id);
+            assert!(
+                line_count >= last_line_count,
+                "a newer snapshot should not lose committed transactions"
+            );
+            last_line_count = line_count;
+            assert_eq!(
+                check_consistency(&snapshot, file_id),
+                0,
+                "a snapshot should not see transactions committed after it was opened"
+            );
+        }
+        writer.join().unwrap();
+    });
+
+    assert_eq!(
+        check_consistency(&snapshot, file_id),
+        0,
+        "a snapshot should not see transactions committed after it was opened"
+    );
+    drop(snapshot);
+    let repository = manager.open_read(repository_id).unwrap();
+    assert_eq!(
+        check_consistency(&repository, file_id),
+        TRANSACTIONS,
+        "a new snapshot should see all the committed transactions"
+    );
+}
+
+#[test]
+fn persistent_snapshot_isolation() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    snapshot_isolation(&manager);
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn memory_snapshot_isolation() {
+    snapshot_isolation(&MemoryRepositoryManager::new());
+}
+
+/// Checks that the heads of the given SVGs are exactly the applied changes
+/// that are not replaced by another applied change.
+fn check_heads<'manager>(repository: &impl Repository<'manager>, single_ids: &[SingleId]) {
+    let applied = repository
+        .changes()
+        .collect::<Result<HashMap<_, _>, _>>()
+        .unwrap();
+    for change in applied.values() {
+        for replaced_hash in change.replace {
+            if let Some(replaced) = applied.get(&replaced_hash) {
+                assert_eq!(
+                    replaced.single_id(),
+                    change.single_id(),
+                    "a change should never replace a change of another SVG"
+                );
+            }
+        }
+    }
+    for &single_id in single_ids {
+        let expected = applied
+            .iter()
+            .filter(|(hash, change)| {
+                change.single_id() == single_id
+                    && !applied
+                        .values()
+                        .any(|other| other.replace.iter().any(|replaced| replaced == **hash))
+            })
+            .map(|(&hash, _)| hash)
+            .collect::<HashSet<ChangeHash>>();
+        assert_eq!(
+            repository.heads(single_id).unwrap(),
+            expected,
+            "the heads should only contain the applied changes that are not replaced"
+        );
+    }
+}
+
+fn random_changes_keep_heads_consistent(manager: &impl RepositoryManager) {
+    let mut repository = manager.open_write(RepositoryId::create_new()).unwrap();
+    let mut rng = rand::thread_rng();
+    let file_id = random_file_id();
+    let line_ids = [random_line_id(), random_line_id()];
+    let single_ids = line_ids.map(|line_id| SingleId::LineExistence(file_id, line_id));
+
+    let mut pool: Vec<Change> = Vec::new();
+    for _ in 0..512 {
+        if pool.is_empty() || rng.gen_bool(0.5) {
+            // Create a new change replacing random changes, possibly of another
+            // SVG, possibly multiple times, possibly not applied
+            let mut replace = StackVec::new();
+            for _ in 0..rng.gen_range(0..=3) {
+                if let Some(replaced) = pool.get(rng.gen_range(0..pool.len().max(1))) {
+                    replace.push(replaced.calculate_hash());
+                }
+            }
+            let change = Change {
+                replace,
+                content: ChangeContent::LineExistence {
+                    file_id,
+                    line_id: line_ids[rng.gen_range(0..line_ids.len())],
+                    existence: rng.gen_bool(0.5),
+                },
+            };
+            let result = repository.apply(change);
+            if change.validate().is_err() {
+                assert!(result.is_err(), "an invalid change should be rejected");
+            }
+            pool.push(change);
+        } else {
+            // Apply or unapply an existing change
+            let change = pool[rng.gen_range(0..pool.len())];
+            if rng.gen_bool(0.5) {
+                let _ = repository.apply(change);
+            } else {
+                repository.unapply(change.calculate_hash()).unwrap();
+            }
+        }
+        check_heads(&repository, &single_ids);
+    }
+    check_history(&repository);
+}
+
+#[test]
+fn persistent_random_changes_keep_heads_consistent() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    random_changes_keep_heads_consistent(&manager);
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn memory_random_changes_keep_heads_consistent() {
+    random_changes_keep_heads_consistent(&MemoryRepositoryManager::new());
+}
+
+fn files_listing(manager: &impl RepositoryManager) {
+    let mut repository = manager.open_write(RepositoryId::create_new()).unwrap();
+    let [first_file, second_file] = [random_file_id(), random_file_id()];
+    assert_eq!(
+        repository.files().unwrap(),
+        HashSet::new(),
+        "a new repository should not have any file"
+    );
+
+    // A file is listed as soon as a change modifies it
+    let first_changes = insert_line(first_file);
+    for change in first_changes {
+        repository.apply(change).unwrap();
+    }
+    let second_change = insert_line(second_file)[0];
+    repository.apply(second_change).unwrap();
+    repository.apply(second_change).unwrap();
+    assert_eq!(
+        repository.files().unwrap(),
+        HashSet::from([first_file, second_file]),
+        "the modified files should be listed"
+    );
+
+    // A file is no longer listed once all its changes are unapplied
+    repository.unapply(second_change.calculate_hash()).unwrap();
+    repository
+        .unapply(first_changes[0].calculate_hash())
+        .unwrap();
+    assert_eq!(
+        repository.files().unwrap(),
+        HashSet::from([first_file]),
+        "a file without applied changes should not be listed"
+    );
+    repository.commit().unwrap();
+}
+
+#[test]
+fn persistent_files_listing() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    files_listing(&manager);
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn memory_files_listing() {
+    files_listing(&MemoryRepositoryManager::new());
+}
+
+fn shared_registry_garbage_collection(manager: &impl RepositoryManager) {
+    let registry = MemoryRegistry::new();
+    let file_id = random_file_id();
+    let line_id = random_line_id();
+    let unused = registry.write(b"unused".as_slice()).unwrap();
+
+    // Two repositories each use their own content of the same registry
+    let mut used = Vec::new();
+    for data in [b"first".as_slice(), b"second".as_slice()] {
+        let content = registry.write(data).unwrap();
+        let mut repository = manager.open_write(RepositoryId::create_new()).unwrap();
+        repository
+            .apply(Change {
+                replace: StackVec::new(),
+                content: ChangeContent::LineContent {
+                    file_id,
+                    line_id,
+                    content,
+                },
+            })
+            .unwrap();
+        repository.commit().unwrap();
+        used.push(content);
+    }
+
+    // Only the content unused by every repository is removed
+    assert_eq!(
+        manager.used_contents().unwrap(),
+        used.iter().copied().collect::<HashSet<_>>(),
+        "the contents of all the repositories should be used"
+    );
+    assert_eq!(
+        manager
+            .collect_garbage(&registry, Duration::ZERO, false)
+            .unwrap(),
+        vec![unused],
+        "only the content unused by every repository should be removed"
+    );
+    for content in used {
+        assert!(
+            registry.exists(content).unwrap(),
+            "the contents used by any repository should be kept"
+        );
+    }
+}
+
+#[test]
+fn persistent_shared_registry_garbage_collection() {
+    let temp_dir = TempDir::new().unwrap();
+    let manager = PersistentRepositoryManager::create(temp_dir.path()).unwrap();
+    shared_registry_garbage_collection(&manager);
+    drop(manager);
+    temp_dir.close().unwrap();
+}
+
+#[test]
+fn memory_shared_registry_garbage_collection() {
+    shared_registry_garbage_collection(&MemoryRepositoryManager::new());
+}
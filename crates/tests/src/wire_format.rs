@@ -0,0 +1,201 @@
+//! Tests pinning the serialization of the data structures against golden
+//! vectors.
+//!
+//! The hashes of changes and contents are derived from their serialized
+//! form, so any drift in the encoding would break every existing hash. If one
+//! of these tests fails, the encoding changed and a migration is needed.
+
+use solipr_core::change::{Change, ChangeContent, ChangeHash, FileId, LineId, SingleId};
+use solipr_core::registry::ContentHash;
+use solipr_core::repository::RepositoryId;
+use solipr_stack::StackVec;
+
+const FILE_ID: &str = "file:0192d5a6-7c1e-7b3a-9f4e-2d8c6b1a0e57";
+const LINE_ID: &str = "line:0192d5a6-7c1e-7b3a-9f4e-5a3b2c1d0e9f";
+const REPOSITORY_ID: &str = "repo:0192d5a6-7c1e-7b3a-9f4e-7e6d5c4b3a29";
+const CONTENT_HASH: &str = "content:LPJNul-wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ";
+
+/// The serialized form and the hash of each change returned by [changes].
+const CHANGE_VECTORS: [(&str, &str); 5] = [
+    (
+        "00000192d5a67c1e7b3a9f4e2d8c6b1a0e570192d5a67c1e7b3a9f4e5a3b2c1d0e9f01",
+        "change:PuEXWATXGbVeh5_IQRGMeP1SvLEs-TcHHh1Hd8rRX_8",
+    ),
+    (
+        "00010192d5a67c1e7b3a9f4e2d8c6b1a0e570192d5a67c1e7b3a9f4e5a3b2c1d0e9f2cf24dba5fb0\
+         a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+        "change:CpuVyCFip_QCbVjOwCKwpD2N7zPfKPL-y_xJP4QjlgY",
+    ),
+    (
+        "00020192d5a67c1e7b3a9f4e2d8c6b1a0e570192d5a67c1e7b3a9f4e5a3b2c1d0e9f000000000000\
+         00000000000000000000",
+        "change:U-dztWV7zoS_0-CEv278wX7-Ce6cCVQ4MR6hQ-7FTZ4",
+    ),
+    (
+        "00030192d5a67c1e7b3a9f4e2d8c6b1a0e570192d5a67c1e7b3a9f4e5a3b2c1d0e9fffffffffffff\
+         ffffffffffffffffffff",
+        "change:kVQidvJ-bN4xZrFby5sO7Hy5JrEMCGvbv4ZHgTaynTg",
+    ),
+    (
+        "013ee1175804d719b55e879fc841118c78fd52bcb12cf937071e1d4777cad15fff000192d5a67c1e\
+         7b3a9f4e2d8c6b1a0e570192d5a67c1e7b3a9f4e5a3b2c1d0e9f00",
+        "change:T36U9QTVHwV2jG_IXiwJhTzrwt4sm5EPVC3V7FJ7ERs",
+    ),
+];
+
+/// The serialized form of each [`SingleId`] variant for [`FILE_ID`] and
+/// [`LINE_ID`].
+///
+/// These are used as keys in the persistent storage.
+const SINGLE_ID_VECTORS: [&str; 4] = [
+    "000192d5a67c1e7b3a9f4e2d8c6b1a0e570192d5a67c1e7b3a9f4e5a3b2c1d0e9f",
+    "010192d5a67c1e7b3a9f4e2d8c6b1a0e570192d5a67c1e7b3a9f4e5a3b2c1d0e9f",
+    "020192d5a67c1e7b3a9f4e2d8c6b1a0e570192d5a67c1e7b3a9f4e5a3b2c1d0e9f",
+    "030192d5a67c1e7b3a9f4e2d8c6b1a0e570192d5a67c1e7b3a9f4e5a3b2c1d0e9f",
+];
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(value: &str) -> Vec<u8> {
+    (0..value.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&value[index..index + 2], 16).unwrap())
+        .collect()
+}
+
+fn changes() -> Vec<Change> {
+    let file_id: FileId = FILE_ID.parse().unwrap();
+    let line_id: LineId = LINE_ID.parse().unwrap();
+    let existence = Change {
+        replace: StackVec::new(),
+        content: ChangeContent::LineExistence {
+            file_id,
+            line_id,
+            existence: true,
+        },
+    };
+    let content = Change {
+        replace: StackVec::new(),
+        content: ChangeContent::LineContent {
+            file_id,
+            line_id,
+            content: CONTENT_HASH.parse().unwrap(),
+        },
+    };
+    let parent = Change {
+        replace: StackVec::new(),
+        content: ChangeContent::LineParent {
+            file_id,
+            line_id,
+            parent: LineId::FIRST,
+        },
+    };
+    let child = Change {
+        replace: StackVec::new(),
+        content: ChangeContent::LineChild {
+            file_id,
+            line_id,
+            child: LineId::LAST,
+        },
+    };
+    let mut replace = StackVec::new();
+    replace.push(existence.calculate_hash());
+    let replacing = Change {
+        replace,
+        content: ChangeContent::LineExistence {
+            file_id,
+            line_id,
+            existence: false,
+        },
+    };
+    vec![existence, content, parent, child, replacing]
+}
+
+#[test]
+fn change_encoding() {
+    for (change, (expected_bytes, expected_hash)) in changes().into_iter().zip(CHANGE_VECTORS) {
+        assert_eq!(
+            hex(&borsh::to_vec(&change).unwrap()),
+            expected_bytes,
+            "the serialization of {change:?} should not change"
+        );
+        assert_eq!(
+            change.calculate_hash().to_string(),
+            expected_hash,
+            "the hash of {change:?} should not change"
+        );
+    }
+}
+
+#[test]
+fn change_decoding() {
+    for (change, (bytes, _)) in changes().into_iter().zip(CHANGE_VECTORS) {
+        assert_eq!(
+            borsh::from_slice::<Change>(&unhex(bytes)).unwrap(),
+            change,
+            "the golden bytes should decode to the same change"
+        );
+    }
+}
+
+#[test]
+fn single_id_encoding() {
+    let file_id: FileId = FILE_ID.parse().unwrap();
+    let line_id: LineId = LINE_ID.parse().unwrap();
+    let single_ids = [
+        SingleId::LineExistence(file_id, line_id),
+        SingleId::LineContent(file_id, line_id),
+        SingleId::LineChild(file_id, line_id),
+        SingleId::LineParent(file_id, line_id),
+    ];
+    for (single_id, expected_bytes) in single_ids.into_iter().zip(SINGLE_ID_VECTORS) {
+        assert_eq!(
+            hex(&borsh::to_vec(&single_id).unwrap()),
+            expected_bytes,
+            "the serialization of {single_id:?} should not change"
+        );
+    }
+}
+
+#[test]
+fn identifier_encoding() {
+    let repository_id: RepositoryId = REPOSITORY_ID.parse().unwrap();
+    assert_eq!(
+        hex(&borsh::to_vec(&repository_id).unwrap()),
+        "0192d5a67c1e7b3a9f4e7e6d5c4b3a29",
+        "a repository id should be serialized as its raw uuid"
+    );
+
+    let content_hash: ContentHash = CONTENT_HASH.parse().unwrap();
+    assert_eq!(
+        hex(&borsh::to_vec(&content_hash).unwrap()),
+        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+        "a content hash should be serialized as its raw bytes"
+    );
+}
+
+#[test]
+fn identifier_display() {
+    for (_, hash) in CHANGE_VECTORS {
+        let change_hash: ChangeHash = hash.parse().unwrap();
+        assert_eq!(
+            change_hash.to_string(),
+            hash,
+            "a change hash should be displayed the way it was parsed"
+        );
+    }
+    for value in [FILE_ID, LINE_ID, REPOSITORY_ID, CONTENT_HASH] {
+        let displayed = match value.split_once(':').unwrap().0 {
+            "file" => value.parse::<FileId>().unwrap().to_string(),
+            "line" => value.parse::<LineId>().unwrap().to_string(),
+            "repo" => value.parse::<RepositoryId>().unwrap().to_string(),
+            _ => value.parse::<ContentHash>().unwrap().to_string(),
+        };
+        assert_eq!(
+            displayed, value,
+            "an identifier should be displayed the way it was parsed"
+        );
+    }
+}